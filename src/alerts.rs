@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use adw::prelude::*;
+use gtk::gio;
+use log::debug;
+
+use crate::apputils;
+use crate::configfile::AlertStyle;
+use crate::AudiosharegtkWindow;
+
+// Sounds from the freedesktop sound theme, shipped with the GNOME runtime
+const SOUND_DIR: &str = "/usr/share/sounds/freedesktop/stereo";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertEvent {
+    ServerError,
+    DeviceConnected,
+    DeviceDisconnected,
+}
+
+impl AlertEvent {
+    fn sound_name(&self) -> &'static str {
+        match self {
+            AlertEvent::ServerError => "dialog-warning.oga",
+            AlertEvent::DeviceConnected => "device-added.oga",
+            AlertEvent::DeviceDisconnected => "device-removed.oga",
+        }
+    }
+}
+
+// Raise the alerts the user picked for an event. They don't depend on the
+// notification daemon, so they still work in kiosks and full screen games.
+pub fn raise(app: &impl IsA<gio::Application>, event: AlertEvent, styles: &[AlertStyle], message: &str) {
+    let Some(win) = app
+        .dynamic_cast_ref::<gtk::Application>()
+        .and_then(|app| app.active_window().or_else(|| app.windows().into_iter().next()))
+        .and_downcast::<AudiosharegtkWindow>()
+    else {
+        return;
+    };
+
+    for style in styles {
+        debug!("Raising {:?} alert for {:?}", style, event);
+        match style {
+            AlertStyle::Sound => {
+                let path = Path::new(SOUND_DIR).join(event.sound_name());
+                if path.exists() {
+                    win.play_alert_sound(&path);
+                } else {
+                    WidgetExt::display(&win).beep();
+                }
+            }
+            // Never present the window, that would steal focus
+            AlertStyle::Attention => {
+                if !win.is_active() {
+                    apputils::show_attention_notification(app, message);
+                }
+            }
+            AlertStyle::Banner => win.show_alert_banner(message),
+        }
+    }
+}
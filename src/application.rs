@@ -22,26 +22,122 @@ use adw::prelude::*;
 use adw::subclass::prelude::*;
 use gettextrs::gettext;
 use gtk::{gio, glib};
+use log::{debug, error, info, warn};
 
 use once_cell::unsync::OnceCell;
 
 use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use crate::audioshare;
+use crate::alerts;
 use crate::apputils;
 use crate::config::VERSION;
-use crate::configfile::{get_config_path, load_or_create_config, save_config};
+use crate::configfile::{self, load_or_create_config, save_config, AlertStyle, AppConfig};
+use crate::devices;
+use crate::diagnostics;
+use crate::firewall;
+use crate::history;
+use crate::log_viewer;
+use crate::notifications;
+use crate::pidfile;
 use crate::AudiosharegtkWindow;
 
+// The alert switches of one event in the preferences, e.g. alert_connect_sound
+fn alert_switches(builder: &gtk::Builder, event: &str) -> Vec<(AlertStyle, adw::SwitchRow)> {
+    AlertStyle::ALL
+        .iter()
+        .map(|style| {
+            let id = format!("alert_{}_{}", event, style.key());
+            let row: adw::SwitchRow = builder.object(&id).unwrap_or_else(|| panic!("Failed to get {}", id));
+            (*style, row)
+        })
+        .collect()
+}
+
+fn set_alert_switches(switches: &[(AlertStyle, adw::SwitchRow)], styles: &[AlertStyle]) {
+    for (style, row) in switches {
+        row.set_active(styles.contains(style));
+    }
+}
+
+// Why start_server() didn't start the server
+#[derive(Debug)]
+enum StartError {
+    AsCmdMissing,
+    InvalidInput(String),
+    // as-cmd hasn't listed the endpoints and encodings yet
+    DevicesNotLoaded,
+    EndpointNotFound(String),
+    EncodingNotFound(String),
+    SpawnFailed(std::io::Error),
+}
+
+impl StartError {
+    fn title(&self) -> String {
+        match self {
+            StartError::InvalidInput(_) => gettext("Invalid Address"),
+            _ => gettext("Cannot Start Server"),
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            StartError::AsCmdMissing => gettext("as-cmd was not found, the server cannot be started"),
+            StartError::InvalidInput(message) => message.clone(),
+            StartError::DevicesNotLoaded => {
+                gettext("The audio endpoints and encodings are still loading.") + " " + &gettext("Please try again in a moment.")
+            }
+            StartError::EndpointNotFound(name) => {
+                gettext("The audio endpoint is no longer available:") + " " + name
+            }
+            StartError::EncodingNotFound(name) => {
+                gettext("The audio encoding is no longer available:") + " " + name
+            }
+            StartError::SpawnFailed(e) => gettext("as-cmd could not be launched:") + " " + &e.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+// Wait a moment before restarting a crashed server
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+// Crashes after running this long don't count towards the restart limit
+const STABLE_RUN_TIME: Duration = Duration::from_secs(60);
+// How long the port spin row has to sit still before the port is saved
+const PORT_APPLY_DELAY: Duration = Duration::from_secs(1);
+
 mod imp {
     use super::*;
 
     #[derive(Debug, Default)]
     pub struct AudiosharegtkApplication {
-        pub is_server_active: Cell<bool>,
         pub audio_share_server_thread: OnceCell<RefCell<audioshare::AudioShareServerThread>>,
         pub test_firewall_thread: OnceCell<RefCell<audioshare::FirewallTestThread>>,
         pub test_firewall_button: RefCell<Option<gtk::Button>>,
+        pub test_firewall_row: RefCell<Option<adw::ActionRow>>,
+        // Devices with a naming prompt open, so reconnects don't stack them
+        pub naming_devices: RefCell<HashSet<String>>,
+        pub notifications: notifications::NotificationCoalescer,
+        // Kept alive to hear about changes made from outside the app
+        pub settings: OnceCell<gio::Settings>,
+        // For the restart policy
+        pub server_started_at: Cell<Option<Instant>>,
+        pub restart_attempts: Cell<u32>,
+        // Settings changed while the server couldn't switch, look again once it runs
+        pub recheck_binding: Cell<bool>,
+        pub audio_devices: audioshare::AudioDeviceCache,
+        // Auto start waits for the first device listing
+        pub pending_auto_start: Cell<bool>,
+        // Set while the dropdowns are refilled, which is not a user choice
+        pub updating_dropdowns: Cell<bool>,
     }
 
     #[glib::object_subclass]
@@ -135,17 +231,39 @@ impl AudiosharegtkApplication {
         self.imp().test_firewall_button.borrow().clone()
     }
 
+    pub fn set_test_firewall_row(&self, row: adw::ActionRow) {
+        *self.imp().test_firewall_row.borrow_mut() = Some(row);
+    }
+
+    pub fn get_test_firewall_row(&self) -> Option<adw::ActionRow> {
+        self.imp().test_firewall_row.borrow().clone()
+    }
+
     pub fn main_window(&self) -> Option<crate::window::AudiosharegtkWindow> {
         self.active_window()
             .and_then(|w| w.downcast::<crate::window::AudiosharegtkWindow>().ok())
     }
 
-    pub fn is_server_active(&self) -> bool {
-        self.imp().is_server_active.get()
+    // None until as-cmd has been checked in the background
+    pub fn as_cmd_status(&self) -> Option<audioshare::AsCmdStatus> {
+        self.imp().audio_devices.get().map(|devices| devices.as_cmd)
     }
 
-    pub fn set_server_active(&self, active: bool) {
-        self.imp().is_server_active.set(active);
+    // A short explanation of what is wrong with as-cmd, None when it is fine
+    // or not checked yet
+    fn as_cmd_problem(&self) -> Option<String> {
+        match self.as_cmd_status()? {
+            audioshare::AsCmdStatus::Supported(_) => None,
+            audioshare::AsCmdStatus::TooOld(version) => Some(
+                gettext("as-cmd") + " " + &version.to_string() + " " + &gettext("is older than the minimum supported version") + " " + &audioshare::MIN_AS_CMD_VERSION.to_string(),
+            ),
+            audioshare::AsCmdStatus::Unknown(_) => Some(gettext("Could not determine the as-cmd version")),
+            audioshare::AsCmdStatus::Missing => Some(gettext("as-cmd was not found, the server cannot be started")),
+        }
+    }
+
+    pub fn server_state(&self) -> audioshare::ServerState {
+        self.imp().audio_share_server_thread.get().unwrap().borrow().state()
     }
 
     // Actions go here
@@ -172,6 +290,58 @@ impl AudiosharegtkApplication {
         let test_firewall = gio::ActionEntry::builder("test_firewall")
             .activate(move |app: &Self, _,_| app.on_test_firewall())
             .build();
+        let pairing_action = gio::ActionEntry::builder("pairing")
+            .activate(move |app: &Self, _, _| app.show_pairing())
+            .build();
+        let log_viewer_action = gio::ActionEntry::builder("log_viewer")
+            .activate(move |app: &Self, _, _| app.show_log_viewer())
+            .build();
+        let export_history_csv = gio::ActionEntry::builder("export_history_csv")
+            .activate(move |app: &Self, _, _| app.action_export_history(false))
+            .build();
+        let export_history_json = gio::ActionEntry::builder("export_history_json")
+            .activate(move |app: &Self, _, _| app.action_export_history(true))
+            .build();
+        let refresh_audio_devices = gio::ActionEntry::builder("refresh_audio_devices")
+            .activate(move |app: &Self, _, _| app.imp().audio_devices.refresh())
+            .build();
+        let diagnostics_action = gio::ActionEntry::builder("diagnostics")
+            .activate(move |app: &Self, _, _| app.action_generate_diagnostics())
+            .build();
+        // Notification buttons
+        let stop_server_action = gio::ActionEntry::builder("stop_server")
+            .activate(move |app: &Self, _, _| {
+                if app.server_state().is_active() {
+                    app.stop_server();
+                }
+            })
+            .build();
+        let retry_server_action = gio::ActionEntry::builder("retry_server")
+            .activate(move |app: &Self, _, _| {
+                if !app.server_state().is_active() {
+                    if let Err(e) = app.start_server() {
+                        app.on_start_error(e);
+                    }
+                }
+            })
+            .build();
+        let server_settings_action = gio::ActionEntry::builder("server_settings")
+            .activate(move |app: &Self, _, _| app.show_server_settings())
+            .build();
+        let block_device_action = gio::ActionEntry::builder("block_device")
+            .parameter_type(Some(&String::static_variant_type()))
+            .activate(move |app: &Self, _, parameter| {
+                if let Some(ip) = parameter.and_then(|p| p.get::<String>()) {
+                    app.action_block_device(ip);
+                }
+            })
+            .build();
+        let add_firewall_rule = gio::ActionEntry::builder("add_firewall_rule")
+            .activate(move |app: &Self, _, _| app.action_change_firewall_rule(true))
+            .build();
+        let remove_firewall_rule = gio::ActionEntry::builder("remove_firewall_rule")
+            .activate(move |app: &Self, _, _| app.action_change_firewall_rule(false))
+            .build();
         self.add_action_entries([
             force_quit_action,
             quit_action,
@@ -180,6 +350,18 @@ impl AudiosharegtkApplication {
             toggle_server_action,
             reset_server_settings,
             test_firewall,
+            refresh_audio_devices,
+            pairing_action,
+            log_viewer_action,
+            diagnostics_action,
+            export_history_csv,
+            export_history_json,
+            stop_server_action,
+            retry_server_action,
+            server_settings_action,
+            block_device_action,
+            add_firewall_rule,
+            remove_firewall_rule,
         ]);
 
         // Setup Keyboard Shortcuts
@@ -195,6 +377,7 @@ impl AudiosharegtkApplication {
             .application_icon("com.subrighteous.audiosharegtk")
             .developer_name("Daniel Rys")
             .version(VERSION)
+            .comments(self.as_cmd_about_text())
             .developers(vec!["Daniel Rys"])
             // Translators: Replace "translator-credits" with your name/username, and optionally an email or URL.
             .translator_credits(&gettext("translator-credits"))
@@ -226,30 +409,164 @@ impl AudiosharegtkApplication {
         about.present(Some(&window));
     }
 
+    fn as_cmd_about_text(&self) -> String {
+        let version = match self.as_cmd_status() {
+            Some(audioshare::AsCmdStatus::Supported(version) | audioshare::AsCmdStatus::TooOld(version)) => version.to_string(),
+            Some(audioshare::AsCmdStatus::Unknown(output)) => output,
+            Some(audioshare::AsCmdStatus::Missing) => gettext("not found"),
+            None => gettext("checking…"),
+        };
+
+        let mut text = gettext("Audio Share server (as-cmd):") + " " + &version;
+        if let Some(problem) = self.as_cmd_problem() {
+            text = text + "\n" + &problem;
+        }
+        text
+    }
+
+    fn show_pairing(&self) {
+        if let Some(win) = self.main_window() {
+            let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
+            let binding = server_thread.binding.lock().unwrap().clone();
+            win.show_pairing_dialog(binding.as_ref());
+        }
+    }
+
+    // Keep an open pairing dialog in sync with the server address
+    fn refresh_pairing(&self) {
+        if let Some(win) = self.main_window() {
+            let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
+            let binding = server_thread.binding.lock().unwrap().clone();
+            win.update_pairing(binding.as_ref());
+        }
+    }
+
+    fn show_log_viewer(&self) {
+        if let Some(win) = self.main_window() {
+            let log_buffer = self
+                .imp()
+                .audio_share_server_thread
+                .get()
+                .unwrap()
+                .borrow()
+                .log_buffer
+                .clone();
+            log_viewer::show_log_viewer(&win, log_buffer);
+        }
+    }
+
+    // Save the whole connection history as CSV or JSON
+    fn action_export_history(&self, json: bool) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+
+        glib::MainContext::default().spawn_local(async move {
+            let records = gio::spawn_blocking(history::load_records).await.unwrap_or_default();
+            let (contents, file_name) = if json {
+                (history::to_json(&records), "audiosharegtk-history.json")
+            } else {
+                (history::to_csv(&records), "audiosharegtk-history.csv")
+            };
+
+            let file_dialog = gtk::FileDialog::builder()
+                .title(gettext("Export History"))
+                .initial_name(file_name)
+                .build();
+
+            let Ok(file) = file_dialog.save_future(Some(&win)).await else {
+                return;
+            };
+
+            if let Some(path) = file.path() {
+                if let Err(e) = std::fs::write(&path, contents) {
+                    error!("Failed to export the history to {:?}: {}", path, e);
+                    apputils::show_alert_dialog(&win, &gettext("Could not export the history"), &e.to_string());
+                }
+            }
+        });
+    }
+
+    // Collect everything useful for a bug report into one text file
+    fn action_generate_diagnostics(&self) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+
+        let snapshot = {
+            let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
+            diagnostics::DiagnosticsSnapshot {
+                config: win.imp().config.get().map(|config| config.borrow().clone()),
+                server_state: server_thread.state(),
+                last_stop_reason: server_thread.last_stop_reason(),
+                as_cmd_log: server_thread.log_buffer.lines(),
+            }
+        };
+
+        glib::MainContext::default().spawn_local(async move {
+            let report = match gio::spawn_blocking(move || diagnostics::generate_report(&snapshot)).await {
+                Ok(report) => report,
+                Err(_) => {
+                    apputils::show_alert_dialog(&win, &gettext("Could not generate diagnostics"), &gettext("Collecting the diagnostics failed, see the log for details."));
+                    return;
+                }
+            };
+
+            let file_name = glib::DateTime::now_local()
+                .and_then(|now| now.format("audiosharegtk-diagnostics-%Y%m%d-%H%M%S.txt"))
+                .map(|name| name.to_string())
+                .unwrap_or_else(|_| "audiosharegtk-diagnostics.txt".to_string());
+
+            let file_dialog = gtk::FileDialog::builder()
+                .title(gettext("Save Diagnostics"))
+                .initial_name(file_name)
+                .build();
+
+            let Ok(file) = file_dialog.save_future(Some(&win)).await else {
+                return;
+            };
+
+            if let Some(path) = file.path() {
+                match std::fs::write(&path, report) {
+                    Ok(_) => info!("Diagnostics written to {:?}", path),
+                    Err(e) => {
+                        error!("Failed to write diagnostics to {:?}: {}", path, e);
+                        apputils::show_alert_dialog(&win, &gettext("Could not save diagnostics"), &e.to_string());
+                    }
+                }
+            }
+        });
+    }
+
     fn action_quit(&self) {
         if let Some(win) = self.main_window() {
             if let Some(config_ref) = win.imp().config.get() {
                 let mut config = config_ref.borrow_mut();
                 if config.minimize_on_exit == true {
                     win.minimize();
-                    println!("Minimizing Window");
+                    debug!("Minimizing Window");
                 } else {
 
-                    println!("Server state is {}" , self.is_server_active());
-                    config.last_server_state = self.is_server_active();
+                    debug!("Server state is {:?}" , self.server_state());
+                    config.last_server_state = self.server_state().is_active();
 
                     // Save the settings
                     let _ = save_config(&config);
-
-                    // Stop the server
-                    self.imp()
-                        .audio_share_server_thread
-                        .get()
-                        .unwrap()
-                        .borrow()
-                        .stop();
-
-                    self.quit();
+                    drop(config);
+
+                    // Stop the server, giving as-cmd the chance to say
+                    // goodbye. That can take a few seconds, so the window
+                    // goes away first.
+                    win.set_visible(false);
+                    let server = self.imp().audio_share_server_thread.get().unwrap().borrow().clone();
+                    glib::MainContext::default().spawn_local(glib::clone!(
+                        #[weak(rename_to = app)]
+                        self,
+                        async move {
+                            let _ = gio::spawn_blocking(move || server.stop_and_wait()).await;
+                            app.quit();
+                        }
+                    ));
                 }
             }
             else{
@@ -301,63 +618,309 @@ impl AudiosharegtkApplication {
             .object("notifications_disconnection")
             .expect("Failed to get notifications_disconnection");
 
+        let error_alert_switches = alert_switches(&builder, "error");
+        let connect_alert_switches = alert_switches(&builder, "connect");
+        let disconnect_alert_switches = alert_switches(&builder, "disconnect");
+
+        let advertise_service_switch: adw::SwitchRow = builder
+            .object("advertise_service_switch")
+            .expect("Failed to get advertise_service_switch");
+
         let test_firewall_button: gtk::Button = builder
             .object("test_firewall_button")
             .expect("test_firewall_button not found");
 
+        let test_firewall_row: adw::ActionRow = builder
+            .object("test_firewall_row")
+            .expect("test_firewall_row not found");
+
+        let firewall_test_timeout_row: adw::SpinRow = builder
+            .object("firewall_test_timeout_row")
+            .expect("firewall_test_timeout_row not found");
+
+        let firewall_rule_row: adw::ActionRow = builder
+            .object("firewall_rule_row")
+            .expect("firewall_rule_row not found");
+
         self.set_test_firewall_button(test_firewall_button.clone());
+        self.set_test_firewall_row(test_firewall_row.clone());
 
-        if let Some(win) = self.main_window() {
-             if let Some(config_ref) = win.clone().imp().config.get() {
-                 let config = config_ref.borrow();
+        // Keep the button in sync with a test started from an earlier dialog
+        if self.imp().test_firewall_thread.get().unwrap().borrow().is_running() {
+            test_firewall_button.set_label(&gettext("Stop Test"));
+            test_firewall_button.add_css_class("error");
+        }
+
+        // Show which firewall the rule buttons will talk to
+        glib::MainContext::default().spawn_local(async move {
+            let backend = gio::spawn_blocking(|| firewall::detect_firewall(&*firewall::default_runner()))
+                .await
+                .unwrap_or(None);
 
+            match backend {
+                Some(backend) => firewall_rule_row.set_subtitle(&(backend.name().to_string() + " " + &gettext("is active"))),
+                None => firewall_rule_row.set_subtitle(&gettext("No supported firewall is active")),
+            }
+        });
+
+        // Every row is bound to its key, so changes are saved as they are made
+        // and on_settings_changed applies them right away
+        let settings = configfile::settings();
+
+        // The radio buttons that have no key of their own
+        do_nothing_check_button.set_active(!(settings.boolean("auto-start-server") || settings.boolean("keep-last-state")));
+        exit_checkbutton.set_active(!settings.boolean("minimize-on-exit"));
+
+        settings.bind("auto-start-server", &start_server_check_button, "active").build();
+        settings.bind("keep-last-state", &keep_last_state_check_button, "active").build();
+        settings.bind("minimize-on-exit", &minimize_to_tray_checkbutton, "active").build();
+        settings.bind("notification-error", &notifications_errors_switch, "active").build();
+        settings.bind("notification-device-connect", &notifications_connection_switch, "active").build();
+        settings.bind("notification-device-disconnect", &notifications_disconnection_switch, "active").build();
+        settings.bind("advertise-service", &advertise_service_switch, "active").build();
+        settings.bind("firewall-test-timeout", &firewall_test_timeout_row, "value").build();
+
+        Self::setup_server_page(&builder, &settings, &self.imp().audio_devices);
+
+        // The alert lists don't map onto a single property, so they are kept
+        // in sync by hand
+        for (key, switches) in [
+            ("error-alerts", error_alert_switches),
+            ("device-connect-alerts", connect_alert_switches),
+            ("device-disconnect-alerts", disconnect_alert_switches),
+        ] {
+            set_alert_switches(&switches, &configfile::alerts_from_settings(&settings, key));
+
+            for (style, row) in switches {
+                row.connect_active_notify(glib::clone!(
+                    #[strong]
+                    settings,
+                    move |row| {
+                        let mut styles = configfile::alerts_from_settings(&settings, key);
+                        styles.retain(|s| *s != style);
+                        if row.is_active() {
+                            styles.push(style);
+                        }
 
-                 do_nothing_check_button
-                 .set_active(!(config.auto_start_server || config.keep_last_state));
+                        if let Err(e) = configfile::set_alerts(&settings, key, &styles) {
+                            warn!("Failed to save {}: {}", key, e);
+                        }
+                    }
+                ));
+            }
+        }
 
-                 keep_last_state_check_button.set_active(config.keep_last_state);
-                 start_server_check_button.set_active(config.auto_start_server);
+        preferences.present(Some(&window));
+    }
 
-                 exit_checkbutton.set_active(!(config.minimize_on_exit));
-                 minimize_to_tray_checkbutton.set_active(config.minimize_on_exit);
+    // The Server page: address, port, default audio and restart policy
+    fn setup_server_page(builder: &gtk::Builder, settings: &gio::Settings, audio_devices: &audioshare::AudioDeviceCache) {
+        let server_address_row: adw::ComboRow = builder
+            .object("server_address_row")
+            .expect("Failed to get server_address_row");
+
+        let server_port_row: adw::SpinRow = builder
+            .object("server_port_row")
+            .expect("Failed to get server_port_row");
+
+        let default_endpoint_row: adw::ComboRow = builder
+            .object("default_endpoint_row")
+            .expect("Failed to get default_endpoint_row");
+
+        let default_encoding_row: adw::ComboRow = builder
+            .object("default_encoding_row")
+            .expect("Failed to get default_encoding_row");
+
+        let restart_on_settings_change_row: adw::SwitchRow = builder
+            .object("restart_on_settings_change_row")
+            .expect("Failed to get restart_on_settings_change_row");
+
+        let restart_on_failure_row: adw::SwitchRow = builder
+            .object("restart_on_failure_row")
+            .expect("Failed to get restart_on_failure_row");
+
+        let restart_attempts_row: adw::SpinRow = builder
+            .object("restart_attempts_row")
+            .expect("Failed to get restart_attempts_row");
+
+        // Saving the port restarts a running server, so it waits until the
+        // user is done clicking the arrows or typing
+        settings.bind("server-port", &server_port_row, "value").flags(gio::SettingsBindFlags::GET).build();
+        let pending_port: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+        server_port_row.connect_value_notify(glib::clone!(
+            #[strong]
+            settings,
+            move |row| {
+                if let Some(source) = pending_port.take() {
+                    source.remove();
+                }
 
-                 notifications_errors_switch.set_active(config.notification_error);
-                 notifications_connection_switch.set_active(config.notification_device_connect);
-                 notifications_disconnection_switch.set_active(config.notification_device_disconnect);
+                let port = row.value() as u16;
+                let source = glib::timeout_add_local_once(PORT_APPLY_DELAY, glib::clone!(
+                    #[strong]
+                    settings,
+                    #[strong]
+                    pending_port,
+                    move || {
+                        pending_port.take();
+                        // Also called when the settings moved the row
+                        if settings.get::<u16>("server-port") != port {
+                            if let Err(e) = settings.set("server-port", port) {
+                                warn!("Failed to save the server port: {}", e);
+                            }
+                        }
+                    }
+                ));
+                pending_port.replace(Some(source));
+            }
+        ));
+        settings.bind("restart-on-settings-change", &restart_on_settings_change_row, "active").build();
+        settings.bind("restart-on-failure", &restart_on_failure_row, "active").build();
+        settings.bind("restart-attempts", &restart_attempts_row, "value").build();
+
+        // Interfaces, plus the saved address if it isn't on any of them
+        let current_ip = settings.string("server-ip").to_string();
+        let mut addresses: Vec<(String, String)> = vec![(gettext("All Interfaces"), "0.0.0.0".to_string())];
+        addresses.extend(audioshare::get_interface_addresses());
+        if !current_ip.is_empty() && !addresses.iter().any(|(_, ip)| *ip == current_ip) {
+            addresses.push((gettext("Custom"), current_ip.clone()));
+        }
 
-                preferences.connect_closed(move |_|{
-                    // Clone a strong reference to the window (so we can use it in the closure)
-                    let window_clone = win.clone();
+        let labels: Vec<String> = addresses.iter().map(|(name, ip)| name.clone() + " (" + ip + ")").collect();
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+        server_address_row.set_model(Some(&gtk::StringList::new(&labels)));
+        if let Some(position) = addresses.iter().position(|(_, ip)| *ip == current_ip) {
+            server_address_row.set_selected(position as u32);
+        }
 
+        server_address_row.connect_selected_notify(glib::clone!(
+            #[strong]
+            settings,
+            move |row| {
+                if let Some((_, ip)) = addresses.get(row.selected() as usize) {
+                    let _ = settings.set_string("server-ip", ip);
+                }
+            }
+        ));
 
-                    if let Some(config_refcell) = window_clone.imp().config.get() {
-                        let mut config = config_refcell.borrow_mut();
+        // as-cmd is slow to answer, so the lists fill in once it has
+        let settings = settings.clone();
+        let mut devices_rx = audio_devices.subscribe();
+        glib::MainContext::default().spawn_local(async move {
+            let devices = loop {
+                if let Some(devices) = devices_rx.borrow_and_update().clone() {
+                    break devices;
+                }
+                if devices_rx.changed().await.is_err() {
+                    return;
+                }
+            };
+
+            let endpoint_position = devices.endpoint_position(&settings.string("audio-endpoint"));
+            let encoding_position = devices.encoding_position(&settings.string("audio-encoding"));
+            let endpoints: Vec<String> = devices.endpoints.into_iter().map(|(_, _, name)| name).collect();
+            let encodings: Vec<String> = devices.encodings.into_iter().map(|(_, description)| description).collect();
+
+            for (row, key, names, position) in [
+                (&default_endpoint_row, "audio-endpoint", endpoints, endpoint_position),
+                (&default_encoding_row, "audio-encoding", encodings, encoding_position),
+            ] {
+                let labels: Vec<&str> = names.iter().map(String::as_str).collect();
+                row.set_model(Some(&gtk::StringList::new(&labels)));
+
+                if let Some(position) = position {
+                    row.set_selected(position);
+                }
 
-                        //Only update if the config is different then the ui
-                        if config.minimize_on_exit != minimize_to_tray_checkbutton.is_active()
-                            || config.keep_last_state != keep_last_state_check_button.is_active()
-                            || config.auto_start_server != start_server_check_button.is_active()
-                            || config.notification_error != notifications_errors_switch.is_active()
-                            || config.notification_device_connect != notifications_connection_switch.is_active()
-                            || config.notification_device_disconnect != notifications_disconnection_switch.is_active()
-                        {
-                            config.minimize_on_exit = minimize_to_tray_checkbutton.is_active();
-                            config.keep_last_state = keep_last_state_check_button.is_active();
-                            config.auto_start_server = start_server_check_button.is_active();
-                            config.notification_error = notifications_errors_switch.is_active();
-                            config.notification_device_connect = notifications_connection_switch.is_active();
-                            config.notification_device_disconnect = notifications_disconnection_switch.is_active();
-
-                            let _ = save_config(&config);
+                row.connect_selected_notify(glib::clone!(
+                    #[strong]
+                    settings,
+                    move |row| {
+                        if let Some(name) = names.get(row.selected() as usize) {
+                            let _ = settings.set_string(key, name);
                         }
-                    } else {
-                        println!("No config set yet.");
                     }
-                });
+                ));
+                row.set_sensitive(true);
             }
+        });
+    }
+
+    // Keep the loaded config in sync with GSettings, whether the change came
+    // from the preferences or from `gsettings set`
+    fn on_settings_changed(&self, settings: &gio::Settings, key: &str) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+        let Some(config_ref) = win.imp().config.get() else {
+            return;
+        };
+
+        // save_config writes while holding the config, which already has the new values
+        let Ok(mut config) = config_ref.try_borrow_mut() else {
+            return;
+        };
+
+        let updated = AppConfig::from_settings(settings);
+        if let Err(e) = updated.validate() {
+            warn!("Ignoring invalid setting {}: {}", key, e);
+            return;
         }
 
-        preferences.present(Some(&window));
+        debug!("Setting changed : {}", key);
+        *config = updated;
+
+        match key {
+            "advertise-service" => self.apply_advertise_service(&config),
+            "server-ip" | "server-port" => {
+                let server_ip = config.server_ip.clone();
+                let server_port = config.server_port.to_string();
+                let restart = config.restart_on_settings_change;
+                drop(config);
+
+                win.imp().server_ip_entry.set_placeholder_text(Some(&server_ip));
+                win.imp().server_ip_entry.set_text(&server_ip);
+                win.imp().server_port_entry.set_placeholder_text(Some(&server_port));
+                win.imp().server_port_entry.set_text(&server_port);
+
+                if restart {
+                    self.restart_server();
+                }
+            }
+            // The dropdown handlers take care of restarting
+            "audio-endpoint" => {
+                let position = self.imp().audio_devices.get().and_then(|devices| devices.endpoint_position(&config.audio_endpoint));
+                drop(config);
+                if let Some(position) = position {
+                    win.imp().audio_endpoint_dropdown.set_selected(position);
+                }
+            }
+            "audio-encoding" => {
+                let position = self.imp().audio_devices.get().and_then(|devices| devices.encoding_position(&config.audio_encoding));
+                drop(config);
+                if let Some(position) = position {
+                    win.imp().audio_encoding_dropdown.set_selected(position);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Publish or withdraw the running server on the local network
+    fn apply_advertise_service(&self, config: &AppConfig) {
+        let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
+        let mut advertiser = server_thread.advertiser.lock().unwrap();
+
+        advertiser.set_enabled(config.advertise_service);
+
+        // Turning it on while the server runs shouldn't wait for a restart
+        if config.advertise_service && !advertiser.is_advertising() {
+            if let Some(binding) = server_thread.binding.lock().unwrap().as_ref() {
+                if let Err(e) = advertiser.advertise(binding) {
+                    error!("Failed to advertise the server: {}", e);
+                }
+            }
+        }
     }
 
     fn on_test_firewall(&self){
@@ -367,15 +930,11 @@ impl AudiosharegtkApplication {
                 let config = config_ref.borrow();
                 let config = config.clone();
 
-                if self.imp()
-                    .audio_share_server_thread
-                    .get()
-                    .unwrap()
-                    .borrow().is_running(){
+                if self.server_state().is_active() {
                     let message:String = gettext("AudioShare Server is running in the background.")
                     + " " + &gettext("Please turn the server off then run the firewall test again.");
 
-                    apputils::show_error_notification(self, &gettext("Server is Running"), &message);
+                    apputils::show_error_notification(self, apputils::FIREWALL_TEST_ID, &gettext("Server is Running"), &message, &[]);
 
                     return;
                 }
@@ -393,16 +952,13 @@ impl AudiosharegtkApplication {
                     .borrow()
                     .stop();
 
-                    if let Some(test_firewall_button) = self.get_test_firewall_button() {
-                        test_firewall_button.set_label("Begin Test");
-                        test_firewall_button.remove_css_class("error");
-                    }
-
-
+                    // The result handler resets the button once the test reports back
                     return;
                 }
 
-                println!("Testing Connection at {}:{}", &config.server_ip, &config.server_port);
+                info!("Testing Connection at {}:{}", &config.server_ip, &config.server_port);
+
+                let timeout = Duration::from_secs(config.firewall_test_timeout);
 
                 // Start Server
                 self.imp()
@@ -413,33 +969,204 @@ impl AudiosharegtkApplication {
                     .start(
                         config.server_ip,
                         config.server_port,
+                        timeout,
                 );
 
                 if let Some(test_firewall_button) = self.get_test_firewall_button() {
-                        test_firewall_button.set_label("Stop Test");
+                        test_firewall_button.set_label(&gettext("Stop Test"));
                         test_firewall_button.add_css_class("error");
                 }
 
+                // Count down in the test row until the result comes in
+                let started = Instant::now();
+                glib::timeout_add_local(
+                    Duration::from_millis(250),
+                    glib::clone!(
+                        #[weak(rename_to = app)]
+                        self,
+                        #[upgrade_or]
+                        glib::ControlFlow::Break,
+                        move || {
+                            if !app.imp().test_firewall_thread.get().unwrap().borrow().is_running() {
+                                return glib::ControlFlow::Break;
+                            }
+
+                            let remaining = timeout.saturating_sub(started.elapsed());
+                            if let Some(row) = app.get_test_firewall_row() {
+                                row.set_subtitle(&(gettext("Waiting for a client…") + " "
+                                    + &remaining.as_secs().to_string() + " " + &gettext("seconds left")));
+                            }
+
+                            glib::ControlFlow::Continue
+                        }
+                    ),
+                );
+
 
             }
         }
 
     }
 
+    // Show the failed test together with the rule the detected firewall needs
+    fn show_firewall_help(&self, title: &str, message: String, server_port: u16) {
+        let app = self.clone();
+        let title = title.to_string();
+
+        glib::MainContext::default().spawn_local(async move {
+            let backend = gio::spawn_blocking(|| firewall::detect_firewall(&*firewall::default_runner()))
+                .await
+                .unwrap_or(None);
+
+            let Some(win) = app.main_window() else {
+                return;
+            };
+
+            let Some(backend) = backend else {
+                apputils::show_alert_dialog(&win, &title, &message);
+                return;
+            };
+
+            let body = message
+                + "\n\n" + &gettext("Detected firewall:") + " " + backend.name()
+                + "\n" + &gettext("Run the following to allow clients through:")
+                + "\n\n" + &backend
+                    .add_rule_steps(server_port)
+                    .iter()
+                    .map(firewall::FirewallStep::display)
+                    .collect::<Vec<String>>()
+                    .join("\n");
+
+            let dialog = adw::AlertDialog::builder()
+                .heading(title.as_str())
+                .body(body.as_str())
+                .build();
+            dialog.add_response("close", &gettext("Close"));
+            dialog.add_response("add", &gettext("Add Rule"));
+            dialog.set_response_appearance("add", adw::ResponseAppearance::Suggested);
+            dialog.set_default_response(Some("add"));
+
+            dialog.connect_response(Some("add"), move |_, _| {
+                app.action_change_firewall_rule(true);
+            });
+
+            dialog.present(Some(&win));
+        });
+    }
+
+    // Add or remove the firewall rule for the configured port.
+    // pkexec asks for authorization through polkit.
+    fn action_change_firewall_rule(&self, add: bool) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+        let Some(config_ref) = win.imp().config.get() else {
+            return;
+        };
+        let server_port = config_ref.borrow().server_port;
+
+        glib::MainContext::default().spawn_local(async move {
+            let result = gio::spawn_blocking(move || {
+                let runner = firewall::default_runner();
+                match firewall::detect_firewall(&*runner) {
+                    Some(backend) if add => firewall::add_rule(&*runner, backend, server_port),
+                    Some(backend) => firewall::remove_rule(&*runner, backend, server_port),
+                    None => Err(gettext("No supported firewall is active")),
+                }
+            })
+            .await
+            .unwrap_or_else(|_| Err(gettext("The firewall command crashed")));
+
+            let title = match (&result, add) {
+                (Ok(_), true) => gettext("Firewall Rule Added"),
+                (Ok(_), false) => gettext("Firewall Rule Removed"),
+                (Err(_), _) => gettext("Could not change the firewall"),
+            };
+
+            let message = match result {
+                Ok(_) => gettext("Port") + " " + &server_port.to_string() + " " + &gettext("was updated for tcp and udp."),
+                Err(e) => e,
+            };
+
+            apputils::show_alert_dialog(&win, &title, &message);
+        });
+    }
+
+    // Bring up the window with the address entry ready to fix
+    fn show_server_settings(&self) {
+        if let Some(win) = self.main_window() {
+            win.present();
+            win.imp().server_ip_entry.grab_focus();
+        }
+    }
+
+    // Keep a client out with a firewall rule. The rule only stops new
+    // connections, so a client that is already streaming keeps going until
+    // it reconnects.
+    fn action_block_device(&self, ip: String) {
+        // The action can be activated over D-Bus with any string
+        let ip = match ip.trim().parse::<std::net::IpAddr>() {
+            Ok(ip) => ip.to_canonical().to_string(),
+            Err(_) => {
+                warn!("Refusing to block {:?}, it is not an IP address", ip);
+                return;
+            }
+        };
+
+        let Some(win) = self.main_window() else {
+            return;
+        };
+        let Some(config_ref) = win.imp().config.get() else {
+            return;
+        };
+        let server_port = config_ref.borrow().server_port;
+
+        glib::MainContext::default().spawn_local(async move {
+            let block_ip = ip.clone();
+            let result = gio::spawn_blocking(move || {
+                let runner = firewall::default_runner();
+                match firewall::detect_firewall(&*runner) {
+                    Some(backend) => firewall::block_source(&*runner, backend, &block_ip, server_port),
+                    None => Err(gettext("No supported firewall is active")),
+                }
+            })
+            .await
+            .unwrap_or_else(|_| Err(gettext("The firewall command crashed")));
+
+            match result {
+                Ok(_) => {
+                    info!("Blocked {} from port {}", ip, server_port);
+                    if let Some(config_ref) = win.imp().config.get() {
+                        let mut config = config_ref.borrow_mut();
+                        if !config.blocked_devices.contains(&ip) {
+                            config.blocked_devices.push(ip.clone());
+                        }
+                        let _ = save_config(&config);
+                    }
+                    apputils::show_alert_dialog(&win, &gettext("Device Blocked"), &(ip + " " + &gettext("can no longer connect to the server.")));
+                }
+                Err(e) => {
+                    error!("Failed to block {}: {}", ip, e);
+                    apputils::show_alert_dialog(&win, &gettext("Could not block the device"), &e);
+                }
+            }
+        });
+    }
+
     fn on_start_up(&self) {
-        println!("On Start Up");
+        debug!("On Start Up");
 
         if let Some(win) = self.main_window() {
             if let Ok(config_file) = load_or_create_config() {
-                println!("Audio Endpoint : {:?}", config_file.audio_endpoint);
-                println!("Audio Encoding : {:?}", config_file.audio_encoding);
-                println!("Server IP : {:?}", config_file.server_ip);
-                println!("Server Port : {:?}", config_file.server_port);
-                println!("minimize_on_exit : {:?}", config_file.minimize_on_exit);
-                println!("auto_start_server : {:?}", config_file.auto_start_server);
-                println!("keep_last_state : {:?}", config_file.keep_last_state);
-                println!("last_server_state : {:?}", config_file.last_server_state);
-                println!("Configuration file Path : {:?}", get_config_path());
+                debug!("Audio Endpoint : {:?}", config_file.audio_endpoint);
+                debug!("Audio Encoding : {:?}", config_file.audio_encoding);
+                debug!("Server IP : {:?}", config_file.server_ip);
+                debug!("Server Port : {:?}", config_file.server_port);
+                debug!("minimize_on_exit : {:?}", config_file.minimize_on_exit);
+                debug!("auto_start_server : {:?}", config_file.auto_start_server);
+                debug!("keep_last_state : {:?}", config_file.keep_last_state);
+                debug!("last_server_state : {:?}", config_file.last_server_state);
+                info!("Settings schema : {}", configfile::SCHEMA_ID);
 
                 win.imp()
                     .server_ip_entry
@@ -448,37 +1175,31 @@ impl AudiosharegtkApplication {
                     .server_port_entry
                     .set_placeholder_text(Some(&config_file.server_port.to_string()));
 
+                self.apply_advertise_service(&config_file);
+
                 // Store the config values in win
                 let _result = win.imp().config.set(RefCell::new(config_file)).unwrap();
 
-            }
-
-            let endpoint_names: Vec<(bool, u16, String)> = audioshare::get_audio_endpoints();
-            let endpoint_names_vec: Vec<&str> = endpoint_names
-                .iter()
-                .map(|(_, _, names)| names.as_str())
-                .collect();
-            let endpoint_names_array: &[&str] = &endpoint_names_vec;
-
-            // Create endpoint model
-            let endpoint_string_list = gtk::StringList::new(&endpoint_names_array);
-            let endpoint_model = endpoint_string_list.clone().upcast::<gio::ListModel>();
-
-            let encodings: Vec<(String, String)> = audioshare::get_audio_encoding();
-            let encoding_names_vec: Vec<&str> =
-                encodings.iter().map(|(_, names)| names.as_str()).collect();
-            let encoding_names_array: &[&str] = &encoding_names_vec;
+                let settings = configfile::settings();
+                settings.connect_changed(None, glib::clone!(
+                    #[weak(rename_to = app)]
+                    self,
+                    move |settings, key| app.on_settings_changed(settings, key)
+                ));
+                let _ = self.imp().settings.set(settings);
 
-            // Create endpoint model
-            let endcoding_string_list = gtk::StringList::new(&encoding_names_array);
-            let endcoding_model = endcoding_string_list.clone().upcast::<gio::ListModel>();
+            }
 
-            win.imp()
-                .audio_endpoint_dropdown
-                .set_model(Some(&endpoint_model));
-            win.imp()
-                .audio_encoding_dropdown
-                .set_model(Some(&endcoding_model));
+            // Check the address as the user types
+            for entry in [&win.imp().server_ip_entry, &win.imp().server_port_entry] {
+                entry.connect_changed(glib::clone!(
+                    #[weak(rename_to = app)]
+                    self,
+                    move |_| {
+                        let _ = app.validate_server_inputs();
+                    }
+                ));
+            }
 
             // Connect the "selected" signal to specific dropdown_change functions
             win.imp().audio_endpoint_dropdown.connect_notify_local(
@@ -531,21 +1252,31 @@ impl AudiosharegtkApplication {
             if let Some(config_data) = win.imp().config.get() {
                 let config = config_data.borrow(); // Get Ref<AppConfig>
 
-                // Set the endpoint and encoding dropdowns to the proper value
-                let endpoint_pos: u32 = audioshare::get_endpoint_position_in_dropdown(&config.audio_endpoint);
-                println!("{} , {}" , endpoint_pos, &config.audio_endpoint);
-                win.imp().audio_endpoint_dropdown.set_selected(endpoint_pos.into());
-
-                let encoding_pos: u32 = audioshare::get_encoding_position_in_dropdown(&config.audio_encoding);
-                println!("{} , {}" , encoding_pos , &config.audio_encoding);
-                win.imp().audio_encoding_dropdown.set_selected(encoding_pos.into());
-
-
-                if config.auto_start_server || (config.keep_last_state && config.last_server_state) {
-                    self.action_toggle_server();
+                if let Some(record) = pidfile::find_orphan() {
+                    // Starting now would fail on the port the old server holds
+                    self.ask_about_orphan(record);
+                } else if config.auto_start_server || (config.keep_last_state && config.last_server_state) {
+                    // It needs as-cmd checked and the lists first
+                    self.imp().pending_auto_start.set(true);
                 }
             }
 
+            // Fill the dropdowns whenever as-cmd has listed what it offers
+            let mut devices_rx = self.imp().audio_devices.subscribe();
+            glib::MainContext::default().spawn_local(glib::clone!(
+                #[weak(rename_to = app)]
+                self,
+                async move {
+                    while devices_rx.changed().await.is_ok() {
+                        let devices = devices_rx.borrow_and_update().clone();
+                        if let Some(devices) = devices {
+                            app.on_audio_devices_changed(&devices);
+                        }
+                    }
+                }
+            ));
+            self.imp().audio_devices.refresh();
+
             // Spawn Listener Tasks Here
             let mut result_rx = self
                 .imp()
@@ -556,14 +1287,18 @@ impl AudiosharegtkApplication {
                 .subscribe_result_event();
 
 
-            // Enroll the "on_server_error" function into the server stop_event
-            let mut rx = self
+            // The UI follows the server state from here on
+            let mut state_rx = self
                 .imp()
                 .audio_share_server_thread
                 .get()
                 .expect("AudioShareServerThread not initialized")
                 .borrow()
-                .subscribe_stop_event();
+                .subscribe_state();
+
+            // The server may already have been started above
+            let mut previous_state = state_rx.borrow_and_update().clone();
+            self.render_server_state(&previous_state);
 
             let mut device_rx = self
                 .imp()
@@ -573,6 +1308,67 @@ impl AudiosharegtkApplication {
                 .borrow()
                 .subscribe_device_event();
 
+            let mut stats_rx = self
+                .imp()
+                .audio_share_server_thread
+                .get()
+                .expect("AudioShareServerThread not initialized")
+                .borrow()
+                .subscribe_stats();
+
+            // Redraw the statistics when they change and every second for the uptime
+            let stats_win = win.downgrade();
+            glib::MainContext::default().spawn_local(async move {
+                loop {
+                    let Some(win) = stats_win.upgrade() else {
+                        break;
+                    };
+                    win.update_stats(&stats_rx.borrow_and_update());
+                    drop(win);
+
+                    tokio::select! {
+                        changed = stats_rx.changed() => {
+                            if changed.is_err() {
+                                break;
+                            }
+                        }
+                        _ = glib::timeout_future_seconds(1) => {}
+                    }
+                }
+            });
+
+            // Fill the history page and keep adding to it. Subscribe before
+            // loading so nothing written in between goes missing.
+            let mut history_rx = self
+                .imp()
+                .audio_share_server_thread
+                .get()
+                .expect("AudioShareServerThread not initialized")
+                .borrow()
+                .history
+                .subscribe();
+
+            let history_win = win.downgrade();
+            glib::MainContext::default().spawn_local(async move {
+                let records = gio::spawn_blocking(history::load_records).await.unwrap_or_default();
+                if let Some(win) = history_win.upgrade() {
+                    for record in &records {
+                        win.add_history_record(record);
+                    }
+                }
+
+                while let Ok(record) = history_rx.recv().await {
+                    let Some(win) = history_win.upgrade() else {
+                        break;
+                    };
+                    // Written while loading, so it was in the file already
+                    if records.contains(&record) {
+                        continue;
+                    }
+                    win.add_history_record(&record);
+                }
+            });
+
             let self_clone = self.clone();
             let app = self.clone();
             let alert_dialog_title_pass = gettext("Firewall Test Passed");
@@ -592,21 +1388,48 @@ impl AudiosharegtkApplication {
                                             let config = config_ref.borrow();
                                             let config = config.clone();
 
-                                            if result {
-                                                apputils::show_alert_dialog(&win, &alert_dialog_title_pass, "Success, clients should be able to connect.");
-                                            }else{
-                                                let message = gettext("Could not retrieve connection from outside clients.")
-                                                + " " +  &gettext("Make sure your app is trying to connect to the server.")
-                                                + " " + &gettext("Check your firewall settings and allow tcp and ucp at")
-                                                + " " + &config.server_ip + ":" + &config.server_port.to_string();
-
-                                                apputils::show_alert_dialog(&win, &alert_dialog_title_fail, &message);
+                                            match result {
+                                                audioshare::FirewallTestResult::Passed { client, elapsed } => {
+                                                    let message = gettext("Success, clients should be able to connect.")
+                                                    + " " + &client.ip().to_string() + " " + &gettext("completed the handshake after")
+                                                    + " " + &format!("{:.1}", elapsed.as_secs_f32()) + " " + &gettext("seconds.");
+
+                                                    apputils::show_alert_dialog(&win, &alert_dialog_title_pass, &message);
+                                                }
+                                                audioshare::FirewallTestResult::TimedOut(timeout) => {
+                                                    let message = gettext("The test timed out after")
+                                                    + " " + &timeout.as_secs().to_string() + " " + &gettext("seconds.")
+                                                    + " " + &gettext("Could not retrieve connection from outside clients.")
+                                                    + " " +  &gettext("Make sure your app is trying to connect to the server.")
+                                                    + " " + &gettext("Check your firewall settings and allow tcp and udp at")
+                                                    + " " + &config.server_ip + ":" + &config.server_port.to_string();
+
+                                                    app.show_firewall_help(&alert_dialog_title_fail, message, config.server_port);
+                                                }
+                                                audioshare::FirewallTestResult::BindFailed => {
+                                                    let message = gettext("Could not listen on")
+                                                    + " " + &config.server_ip + ":" + &config.server_port.to_string() + ". "
+                                                    + &gettext("Please check the ip address and port then try again.");
+
+                                                    apputils::show_alert_dialog(&win, &alert_dialog_title_fail, &message);
+                                                }
+                                                audioshare::FirewallTestResult::Cancelled => {}
                                             }
 
                                             if let Some(test_firewall_button) = app.get_test_firewall_button() {
-                                                test_firewall_button.set_label("Begin Test");
+                                                test_firewall_button.set_label(&gettext("Begin Test"));
                                                 test_firewall_button.remove_css_class("error");
                                             }
+
+                                            if let Some(row) = app.get_test_firewall_row() {
+                                                let summary = match result {
+                                                    audioshare::FirewallTestResult::Passed { .. } => gettext("Test passed"),
+                                                    audioshare::FirewallTestResult::TimedOut(_) => gettext("Test timed out"),
+                                                    audioshare::FirewallTestResult::Cancelled => gettext("Test cancelled"),
+                                                    audioshare::FirewallTestResult::BindFailed => gettext("Test failed to start"),
+                                                };
+                                                row.set_subtitle(&summary);
+                                            }
                                         }
                                     }
 
@@ -619,31 +1442,10 @@ impl AudiosharegtkApplication {
                                 self_clone.on_device_connect(device_ip, connect_status);
                             }
 
-                            Ok(_) = rx.changed() => {
-                                if let Some(reason) = rx.borrow().as_ref() {
-                                    println!("Process stopped: {:?}", reason);
-                                    // handle reason...
-                                    if reason != &audioshare::ProcessStopReason::ExitedSuccessfully {
-                                        self_clone.on_server_error(reason);
-                                    } else {
-                                        if let Some(win) = self_clone.main_window() {
-                                            if let Some(config_data) = win.imp().config.get() {
-                                                let mut config = config_data.borrow_mut(); // Get Ref<AppConfig>
-                                                // TODO : After starting the server save config to file
-                                                config.server_ip = win.imp().server_ip_entry.text().to_string();
-                                                config.server_port = win.imp().server_port_entry.text().to_string().parse().unwrap_or(config.server_port);
-
-                                                let endpoint_selected_name = Self::get_selected_string_from_dropdown(&win.imp().audio_endpoint_dropdown);
-                                                config.audio_endpoint = endpoint_selected_name.expect("Failed to get endpoint dropdown string");
-
-                                                let encoding_selected_name = Self::get_selected_string_from_dropdown(&win.imp().audio_encoding_dropdown);
-                                                config.audio_encoding = encoding_selected_name.expect("Failed to get encoding dropdown string");
-
-                                                let _ = save_config(&config);
-                                            }
-                                        }
-                                    }
-                                }
+                            Ok(_) = state_rx.changed() => {
+                                let state = state_rx.borrow_and_update().clone();
+                                self_clone.on_server_state_changed(&previous_state, &state);
+                                previous_state = state;
                             }
                         }
                     }
@@ -652,219 +1454,292 @@ impl AudiosharegtkApplication {
         }
     }
 
-    fn action_stop_server(&self, reason : audioshare::ProcessStopReason){
-        if self.is_server_active() == true {
-            println!("Stopping the server");
-            self.set_server_active(false);
+    // Everything the UI does about the server follows from its state
+    fn on_server_state_changed(&self, previous: &audioshare::ServerState, state: &audioshare::ServerState) {
+        info!("Server state changed from {:?} to {:?}", previous, state);
+        self.render_server_state(state);
 
-            if let Some(win) = self.main_window() {
-                win.imp().toggle_server.set_label(&gettext("Start"));
-                win.imp().toggle_server.add_css_class("success");
-                win.imp().toggle_server.remove_css_class("error");
-
-                win.imp().server_ip_entry.set_editable(true);
-                win.imp().server_port_entry.set_editable(true);
+        // as-cmd takes its clients down with it without a word
+        if previous == &audioshare::ServerState::Running && state != &audioshare::ServerState::Running {
+            self.imp().notifications.reset();
+        }
 
-                win.imp().server_ip_entry.set_secondary_icon_name(None);
-                win.imp().server_port_entry.set_secondary_icon_name(None);
+        if !state.is_active() {
+            self.imp().recheck_binding.set(false);
+        }
 
-                if reason == audioshare::ProcessStopReason::Resetting{
-                    // Tell server to reset()
-                    self.imp()
-                        .audio_share_server_thread
-                        .get()
-                        .unwrap()
-                        .borrow()
-                        .reset();
-                }
-                else{
-                    // Stop the server
-                    self.imp()
-                        .audio_share_server_thread
-                        .get()
-                        .unwrap()
-                        .borrow()
-                        .stop();
+        match state {
+            audioshare::ServerState::Running => {
+                self.imp().server_started_at.set(Some(Instant::now()));
+                self.refresh_pairing();
+                if self.imp().recheck_binding.take() {
+                    self.restart_server();
                 }
-
             }
+            audioshare::ServerState::Stopped => {
+                self.refresh_pairing();
+                if previous != &audioshare::ServerState::Stopped {
+                    self.save_server_settings();
+                }
+            }
+            // Whoever called start_server() tells the user about it
+            audioshare::ServerState::Failed(audioshare::ProcessStopReason::FailedToStart) => {
+                self.refresh_pairing();
+            }
+            audioshare::ServerState::Failed(reason @ audioshare::ProcessStopReason::ExitedWithError(_)) => {
+                self.refresh_pairing();
+                self.on_server_exited_unexpectedly(reason);
+            }
+            audioshare::ServerState::Failed(reason) => {
+                self.refresh_pairing();
+                self.on_server_error(reason);
+            }
+            _ => {}
         }
     }
 
-    // Toggle/Start Server
-    fn action_toggle_server(&self) {
-        if self.is_server_active() == true {
-            println!("Stopping the server");
-            self.set_server_active(false);
-
-            if let Some(win) = self.main_window() {
-                win.imp().toggle_server.set_label(&gettext("Start"));
-                win.imp().toggle_server.add_css_class("success");
-                win.imp().toggle_server.remove_css_class("error");
+    fn render_server_state(&self, state: &audioshare::ServerState) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+
+        let label = match state {
+            audioshare::ServerState::Stopped | audioshare::ServerState::Failed(_) => gettext("Start"),
+            audioshare::ServerState::Starting => gettext("Starting…"),
+            audioshare::ServerState::Running => gettext("Stop"),
+            audioshare::ServerState::Stopping => gettext("Stopping…"),
+            audioshare::ServerState::Restarting => gettext("Restarting…"),
+        };
+        win.imp().toggle_server.set_label(&label);
+
+        let active = state.is_active();
+        if active {
+            win.imp().toggle_server.remove_css_class("success");
+            win.imp().toggle_server.add_css_class("error");
+        } else {
+            win.imp().toggle_server.add_css_class("success");
+            win.imp().toggle_server.remove_css_class("error");
+        }
 
-                win.imp().server_ip_entry.set_editable(true);
-                win.imp().server_port_entry.set_editable(true);
+        let lock_icon = if active { Some("changes-prevent-symbolic") } else { None };
+        win.imp().server_ip_entry.set_editable(!active);
+        win.imp().server_ip_entry.set_secondary_icon_name(lock_icon);
+        win.imp().server_port_entry.set_editable(!active);
+        win.imp().server_port_entry.set_secondary_icon_name(lock_icon);
 
-                win.imp().server_ip_entry.set_secondary_icon_name(None);
-                win.imp().server_port_entry.set_secondary_icon_name(None);
+        let _ = self.validate_server_inputs();
+    }
 
-                // Stop the server
-                self.imp()
-                    .audio_share_server_thread
-                    .get()
-                    .unwrap()
-                    .borrow()
-                    .stop();
-            }
-        } else {
-            if let Some(win) = self.main_window(){
-            //if let Ok(_) = self.main_window().expect("idk and don't care").imp().server_port_entry.text().parse::<u16>() || {
-            if let Ok(_) = win.imp().server_port_entry.text().parse::<u16>(){
-                println!("Valid 1");
-            }else{
-
-                if !win.imp().server_port_entry.text().is_empty(){
-                    apputils::show_error_notification(
-                        self,
-                        &gettext("Invalid Port"),
-                        &gettext("Please enter a number between 0 and 65535."),
-                    );
-                    return;
-                }
+    // Remember what the server last ran with
+    fn save_server_settings(&self) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+        let Some(config_data) = win.imp().config.get() else {
+            return;
+        };
+
+        let mut config = config_data.borrow_mut(); // Get Ref<AppConfig>
+        config.server_ip = win.imp().server_ip_entry.text().to_string();
+        config.server_port = win.imp().server_port_entry.text().to_string().parse().unwrap_or(config.server_port);
+
+        if let Some(endpoint_selected_name) = Self::get_selected_string_from_dropdown(&win.imp().audio_endpoint_dropdown) {
+            config.audio_endpoint = endpoint_selected_name;
+        }
+        if let Some(encoding_selected_name) = Self::get_selected_string_from_dropdown(&win.imp().audio_encoding_dropdown) {
+            config.audio_encoding = encoding_selected_name;
+        }
 
-                if let Some(placeholder) = win.imp().server_port_entry.placeholder_text(){
+        let _ = save_config(&config);
+    }
 
-                    if let Ok(_) = placeholder.to_string().parse::<u16>(){
-                        win.imp().server_port_entry.set_text(&placeholder.to_string());
-                    }
-                    else{
-                        return;
-                    }
+    // Mark invalid entries and only allow starting the server when both are
+    // valid. Empty entries are fine, they fall back to the settings.
+    fn validate_server_inputs(&self) -> Result<(), String> {
+        let Some(win) = self.main_window() else {
+            return Ok(());
+        };
+
+        let ip = win.imp().server_ip_entry.text();
+        let port = win.imp().server_port_entry.text();
+        let ip_result = if ip.is_empty() { Ok(()) } else { configfile::validate_server_ip(&ip) };
+        let port_result = if port.is_empty() { Ok(()) } else { configfile::validate_server_port(&port).map(|_| ()) };
+
+        for (entry, result) in [(&win.imp().server_ip_entry, &ip_result), (&win.imp().server_port_entry, &port_result)] {
+            match result {
+                Ok(()) => {
+                    entry.remove_css_class("error");
+                    entry.set_tooltip_text(None);
                 }
-                else{
-                    return;
+                Err(message) => {
+                    entry.add_css_class("error");
+                    entry.set_tooltip_text(Some(message));
                 }
             }
+        }
 
+        let result = ip_result.and(port_result);
 
+        // Stopping must always be possible
+        if let Some(action) = self.lookup_action("toggle_server").and_downcast::<gio::SimpleAction>() {
+            let state = self.server_state();
+            action.set_enabled((result.is_ok() || state.is_active()) && !state.is_busy());
+        }
 
+        result
+    }
 
-            println!("Starting the server");
-            self.set_server_active(true);
-
-            if let Some(win) = self.main_window() {
-                win.imp().toggle_server.set_label(&gettext("Stop"));
-                win.imp().toggle_server.remove_css_class("success");
-                win.imp().toggle_server.add_css_class("error");
-
-                win.imp().server_ip_entry.set_editable(false);
-                win.imp().server_ip_entry.set_secondary_icon_name(Some("changes-prevent-symbolic"));
-                win.imp().server_port_entry.set_editable(false);
-                win.imp().server_port_entry.set_secondary_icon_name(Some("changes-prevent-symbolic"));
-
-                if win.imp().server_ip_entry.text().is_empty() {
-                    if let Some(placeholder) = win.imp().server_ip_entry.placeholder_text() {
-                        win.imp().server_ip_entry.set_text(&placeholder);
-                    }
-                }
+    // Toggle/Start Server
+    fn action_toggle_server(&self) {
+        if self.server_state().is_active() {
+            self.stop_server();
+        } else if let Err(e) = self.start_server() {
+            self.on_start_error(e);
+        }
+    }
 
-                if win.imp().server_port_entry.text().is_empty() {
-                    if let Some(placeholder) = win.imp().server_port_entry.placeholder_text() {
-                        win.imp().server_port_entry.set_text(&placeholder);
-                    }
-                }
+    fn stop_server(&self) {
+        info!("Stopping the server");
+        self.imp()
+            .audio_share_server_thread
+            .get()
+            .unwrap()
+            .borrow()
+            .stop();
+    }
 
-                // Get the endpoint and encoding settings from the ui
-                let endpoint_selected_name =
-                    Self::get_selected_string_from_dropdown(&win.imp().audio_endpoint_dropdown);
-                println!(
-                    "{}",
-                    endpoint_selected_name
-                        .clone()
-                        .expect("selected name is None")
-                        .to_string()
-                );
+    fn start_server(&self) -> Result<(), StartError> {
+        if let Some(audioshare::AsCmdStatus::Missing) = self.as_cmd_status() {
+            return Err(StartError::AsCmdMissing);
+        }
 
-                let endpoint_id: u32 = audioshare::get_endpoint_id(
-                    &endpoint_selected_name
-                        .expect("selected name number is none")
-                        .to_string(),
-                )
-                .expect("selected_names doesn't exist");
-                println!("{}", endpoint_id);
-
-                let encoding_selected_name = Self::get_selected_string_from_dropdown(&win.imp().audio_encoding_dropdown);
-                println!(
-                    "{}",
-                    encoding_selected_name
-                        .clone()
-                        .expect("selected name is None")
-                        .to_string()
-                );
+        let Some(win) = self.main_window() else {
+            return Ok(());
+        };
 
-                let encoding_key: String = audioshare::get_encoding_key(
-                    &encoding_selected_name
-                        .expect("selected name number is none")
-                        .to_string(),
-                )
-                .expect("selected_names doesn't exist");
-                println!("{}", encoding_key);
+        // The button is disabled while the inputs are invalid, but the
+        // shortcut and notification buttons can still get here
+        self.validate_server_inputs().map_err(StartError::InvalidInput)?;
 
-                // Convert server_port string to u16
-                let server_port_string = win.imp().server_port_entry.text().to_string();
+        if win.imp().server_port_entry.text().is_empty() {
+            // An empty entry means the port from the settings
+            if let Some(config) = win.imp().config.get() {
+                let server_port = config.borrow().server_port;
+                win.imp().server_port_entry.set_text(&server_port.to_string());
+            }
+        }
 
+        if win.imp().server_ip_entry.text().is_empty() {
+            if let Some(config) = win.imp().config.get() {
+                win.imp().server_ip_entry.set_text(&config.borrow().server_ip);
+            }
+        }
 
-                let server_port: u16 = server_port_string
-                    .parse()
-                    .expect("Failed to convert server port to u16");
+        let server_ip = win.imp().server_ip_entry.text().to_string();
+        let server_port = configfile::validate_server_port(&win.imp().server_port_entry.text())
+            .map_err(StartError::InvalidInput)?;
+
+        let devices = self.imp().audio_devices.get().ok_or(StartError::DevicesNotLoaded)?;
+
+        // Get the endpoint and encoding settings from the ui
+        let endpoint_name = Self::get_selected_string_from_dropdown(&win.imp().audio_endpoint_dropdown).unwrap_or_default();
+        debug!("Selected endpoint : {}", endpoint_name);
+        let endpoint_id = devices
+            .endpoint_id(&endpoint_name)
+            .ok_or(StartError::EndpointNotFound(endpoint_name))?;
+        debug!("Endpoint id : {}", endpoint_id);
+
+        let encoding_name = Self::get_selected_string_from_dropdown(&win.imp().audio_encoding_dropdown).unwrap_or_default();
+        debug!("Selected encoding : {}", encoding_name);
+        let encoding_key = devices
+            .encoding_key(&encoding_name)
+            .ok_or(StartError::EncodingNotFound(encoding_name))?;
+        debug!("Encoding key : {}", encoding_key);
+
+        info!("Starting the server");
+        self.imp()
+            .audio_share_server_thread
+            .get()
+            .unwrap()
+            .borrow()
+            .start(server_ip, server_port, endpoint_id, encoding_key)
+            .map_err(StartError::SpawnFailed)
+    }
 
-                // Start Server
-                self.imp()
-                    .audio_share_server_thread
-                    .get()
-                    .unwrap()
-                    .borrow()
-                    .start(
-                        win.imp().server_ip_entry.text().to_string(),
-                        server_port,
-                        endpoint_id,
-                        encoding_key,
-                    );
+    // Tell the user why the server didn't start and put the controls back
+    fn on_start_error(&self, error: StartError) {
+        error!("Failed to start the server: {}", error);
 
+        let mut buttons = Vec::new();
+        match error {
+            StartError::InvalidInput(_) | StartError::EndpointNotFound(_) | StartError::EncodingNotFound(_) => {
+                buttons.push(apputils::NotificationButton::new(gettext("Open Settings"), "app.server_settings"));
+            }
+            StartError::DevicesNotLoaded => {
+                self.imp().audio_devices.refresh();
             }
+            StartError::SpawnFailed(_) => {
+                buttons.push(apputils::NotificationButton::new(gettext("Retry"), "app.retry_server"));
+            }
+            StartError::AsCmdMissing => {}
         }
+
+        apputils::show_error_notification(
+            self,
+            apputils::SERVER_START_ID,
+            &error.title(),
+            &error.message(),
+            &buttons,
+        );
+
+        // A restart took the old process down already, so finish it as a stop
+        if self.server_state().is_active() {
+            self.stop_server();
+        } else {
+            self.render_server_state(&self.server_state());
         }
     }
 
     fn on_device_connect(&self, device_ip: String , connected: bool){
 
-        let notification = gio::Notification::new("audio_share_info");
-        notification.set_icon(&gio::ThemedIcon::new(
-            "com.subrighteous.audiosharegtk",
-        ));
-        let message;
-        let title;
-
-        if connected {
-            title = gettext("Device Connected");
-            message = device_ip.clone() + " " + &gettext("connected to the server");
-        }
-        else{
-            title = gettext("Device Disconnected");
-            message = device_ip.clone() + " " + &gettext("disconnected from the server");
-        }
-
         if let Some(win) = self.main_window() {
 
             if let Some(config_data) = win.imp().config.get() {
-                let config = config_data.borrow_mut(); // Get Ref<AppConfig>
-                if config.notification_device_connect && connected{
-                    apputils::show_connection_notification(self, &title , &message, &connected);
+                let config = config_data.borrow(); // Get Ref<AppConfig>
+
+                if config.blocked_devices.contains(&device_ip) {
+                    info!("Blocked device {} connected, the firewall rule is not active yet", device_ip);
+                    return;
                 }
-                if config.notification_device_disconnect && !connected{
-                    apputils::show_connection_notification(self, &title , &message, &connected);
+
+                // The ARP table is read once per connect, the stats and the
+                // disconnect reuse what it said
+                let cached_key = win.imp().device_keys.borrow().get(&device_ip).cloned();
+                let device_key = match cached_key {
+                    Some(key) if !connected => key,
+                    _ => devices::device_key(&device_ip),
+                };
+                if connected {
+                    win.imp().device_keys.borrow_mut().insert(device_ip.clone(), device_key.clone());
                 }
+                let device_name = devices::display_name(&config.device_names, &device_key, &device_ip);
+                let is_known = devices::nickname(&config.device_names, &device_key).is_some();
+
+                // Flaky clients come and go, so the coalescer decides what is worth showing
+                self.imp().notifications.push(self, notifications::DeviceEvent {
+                    key: device_key.clone(),
+                    ip: device_ip.clone(),
+                    name: device_name,
+                    connected,
+                    known: is_known,
+                    notify: if connected { config.notification_device_connect } else { config.notification_device_disconnect },
+                    alerts: if connected { config.device_connect_alerts.clone() } else { config.device_disconnect_alerts.clone() },
+                });
 
+                if connected && !config.device_names.contains_key(&device_key) {
+                    drop(config);
+                    self.ask_device_name(device_key, device_ip);
+                }
             }
 
         }
@@ -872,19 +1747,88 @@ impl AudiosharegtkApplication {
 
     }
 
+    // Offer to name a device the first time it connects
+    fn ask_device_name(&self, device_key: String, device_ip: String) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+
+        if !self.imp().naming_devices.borrow_mut().insert(device_key.clone()) {
+            return;
+        }
+
+        let entry = gtk::Entry::builder()
+            .placeholder_text(gettext("e.g. Living Room TV"))
+            .activates_default(true)
+            .build();
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("New Device"))
+            .body(device_ip + " " + &gettext("connected for the first time. Give it a name to recognize it later."))
+            .extra_child(&entry)
+            .build();
+        dialog.add_response("skip", &gettext("Don't Name"));
+        dialog.add_response("save", &gettext("Save"));
+        dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("save"));
+        dialog.set_close_response("skip");
+
+        dialog.connect_response(None, glib::clone!(
+            #[weak(rename_to = app)]
+            self,
+            #[weak]
+            win,
+            move |_, response| {
+                app.imp().naming_devices.borrow_mut().remove(&device_key);
+
+                // Remember skipped devices too so they are not asked about again
+                let name = if response == "save" {
+                    entry.text().trim().to_string()
+                } else {
+                    String::new()
+                };
+
+                if let Some(config_ref) = win.imp().config.get() {
+                    let mut config = config_ref.borrow_mut();
+                    config.device_names.insert(device_key.clone(), name);
+                    if let Err(e) = save_config(&config) {
+                        error!("Failed to save the device name: {}", e);
+                    }
+                }
+            }
+        ));
+
+        dialog.present(Some(&win));
+    }
+
     fn on_server_error(&self, reason: &audioshare::ProcessStopReason) {
         let mut title: String = String::new();
         let mut message: String = String::new();
+        let mut buttons = Vec::new();
 
         if reason == &audioshare::ProcessStopReason::InvalidArgument {
             title = gettext("Invalid ip address");
             message = gettext("Please check the ip address and port then try again.");
+            buttons.push(apputils::NotificationButton::new(gettext("Open Settings"), "app.server_settings"));
+        }
+
+        if let audioshare::ProcessStopReason::ExitedWithError(_) = reason {
+            title = gettext("Server stopped unexpectedly");
+            message = gettext("as-cmd exited on its own. Check the server log for details.");
+            buttons.push(apputils::NotificationButton::new(gettext("Retry"), "app.retry_server"));
+        }
+
+        if reason == &audioshare::ProcessStopReason::FailedToKill {
+            title = gettext("Could not stop the server");
+            message = gettext("as-cmd did not exit when asked to and may still be using the port.");
         }
 
         if reason == &audioshare::ProcessStopReason::InvalidBinding {
             let title_text  = gettext("Cannot assign requested address");
             title = title_text;
             message = gettext("Please check the ip address and port then try again.");
+            buttons.push(apputils::NotificationButton::new(gettext("Retry"), "app.retry_server"));
+            buttons.push(apputils::NotificationButton::new(gettext("Open Settings"), "app.server_settings"));
         }
 
         //
@@ -894,59 +1838,306 @@ impl AudiosharegtkApplication {
                 let config = config_data.borrow_mut(); // Get Ref<AppConfig>
                 if config.notification_error{
 
-                    apputils::show_error_notification(self, &title, &message);
+                    apputils::show_error_notification(self, apputils::SERVER_ERROR_ID, &title, &message, &buttons);
                     //self.send_notification(Some("com.subrighteous.audiosharegtk"), &notification);
                 }
 
+                alerts::raise(self, alerts::AlertEvent::ServerError, &config.error_alerts, &title);
+
             }
+        }
+    }
 
-            win.imp().toggle_server.set_label("Start");
-            win.imp().toggle_server.add_css_class("success");
-            win.imp().toggle_server.remove_css_class("error");
+    // Put the new lists in the dropdowns, keeping what the running server
+    // uses or else what the settings say selected
+    fn on_audio_devices_changed(&self, devices: &audioshare::AudioDevices) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+
+        let running = self.imp().audio_share_server_thread.get().unwrap().borrow().binding.lock().unwrap().clone();
+        let (endpoint_position, encoding_position) = match running {
+            Some(binding) => (
+                devices.endpoint_position_by_id(binding.endpoint_id),
+                devices.encoding_position_by_key(&binding.encoding_key),
+            ),
+            None => match win.imp().config.get() {
+                Some(config) => {
+                    let config = config.borrow();
+                    (
+                        devices.endpoint_position(&config.audio_endpoint),
+                        devices.encoding_position(&config.audio_encoding),
+                    )
+                }
+                None => (None, None),
+            },
+        };
+        debug!("Endpoint {:?}, encoding {:?}", endpoint_position, encoding_position);
+
+        let endpoint_names: Vec<&str> = devices.endpoints.iter().map(|(_, _, name)| name.as_str()).collect();
+        let encoding_names: Vec<&str> = devices.encodings.iter().map(|(_, name)| name.as_str()).collect();
+
+        self.imp().updating_dropdowns.set(true);
+        win.imp().audio_endpoint_dropdown.set_model(Some(&gtk::StringList::new(&endpoint_names)));
+        win.imp().audio_endpoint_dropdown.set_selected(endpoint_position.unwrap_or(0));
+        win.imp().audio_encoding_dropdown.set_model(Some(&gtk::StringList::new(&encoding_names)));
+        win.imp().audio_encoding_dropdown.set_selected(encoding_position.unwrap_or(0));
+        self.imp().updating_dropdowns.set(false);
+
+        // Warn up front instead of when the server fails to start
+        let problem = self.as_cmd_problem();
+        win.imp().as_cmd_banner.set_title(problem.as_deref().unwrap_or_default());
+        win.imp().as_cmd_banner.set_revealed(problem.is_some());
+
+        if self.imp().pending_auto_start.take() && devices.as_cmd != audioshare::AsCmdStatus::Missing {
+            self.action_toggle_server();
+        }
+    }
 
-            win.imp().server_ip_entry.set_editable(true);
-            win.imp().server_port_entry.set_editable(true);
+    fn on_endpoint_dropdown_change(&self, _selected: &String) {
+        debug!("on_endpoint_dropdown_change : {}", _selected);
 
-            win.imp().server_ip_entry.set_secondary_icon_name(None);
-            win.imp().server_port_entry.set_secondary_icon_name(None);
+        if self.imp().updating_dropdowns.get() {
+            return;
         }
 
-        self.set_server_active(false);
+        if self.restart_on_settings_change() {
+            self.restart_server();
+        }
     }
 
-    fn on_endpoint_dropdown_change(&self, _selected: &String) {
-        println!("on_endpoint_dropdown_change : {}", _selected);
+    fn on_encoding_dropdown_change(&self, _selected: &String) {
+        debug!("on_encoding_dropdown_change : {}", _selected);
 
-        let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
+        if self.imp().updating_dropdowns.get() {
+            return;
+        }
 
-        if server_thread.is_running() {
-            // Turn off then on
-            self.action_toggle_server();
-            self.action_toggle_server();
+        if self.restart_on_settings_change() {
+            self.restart_server();
         }
     }
 
-    fn on_encoding_dropdown_change(&self, _selected: &String) {
-        println!("on_encoding_dropdown_change : {}", _selected);
+    fn restart_on_settings_change(&self) -> bool {
+        self.main_window()
+            .and_then(|win| win.imp().config.get().map(|config| config.borrow().restart_on_settings_change))
+            .unwrap_or(true)
+    }
 
+    // Move the running server over to the current settings without going
+    // through the stopped state
+    fn restart_server(&self) {
         let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
+        let state = server_thread.state();
+        if !state.is_active() {
+            return;
+        }
 
-        if server_thread.is_running() {
-            // Turn off then on
-            self.action_toggle_server();
-            self.action_toggle_server();
+        let Some(binding) = self.selected_binding() else {
+            warn!("Not switching the server, the selected settings are incomplete");
+            return;
+        };
+
+        if state == audioshare::ServerState::Running && server_thread.binding.lock().unwrap().as_ref() == Some(&binding) {
+            debug!("The server already uses the selected settings");
+            return;
+        }
+
+        let Some(done) = server_thread.reconfigure() else {
+            // Starting, or coming back from a crash with whatever settings
+            // were current when it started
+            info!("The server is {:?}, switching to {:?} once it runs", state, binding);
+            self.imp().recheck_binding.set(true);
+            return;
+        };
+        drop(server_thread);
+
+        // as-cmd has no way to ask its clients to reconnect, they only see
+        // the connection drop and have to connect again themselves
+        info!("Switching the server to {:?}", binding);
+
+        // Started from here rather than the server thread, see AudioShareServerThread::spawn
+        let app = self.downgrade();
+        glib::MainContext::default().spawn_local(async move {
+            if done.await.is_err() {
+                warn!("Lost track of the old as-cmd process, starting the new one anyway");
+            }
+            if let Some(app) = app.upgrade() {
+                // The user may have stopped it in the meantime
+                if app.server_state() == audioshare::ServerState::Restarting {
+                    if let Err(e) = app.start_server() {
+                        app.on_start_error(e);
+                    }
+                }
+            }
+        });
+    }
+
+    // An as-cmd from a session that crashed is still running and holding
+    // the port. Let the user keep it or stop it.
+    fn ask_about_orphan(&self, record: pidfile::PidRecord) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(gettext("Server Still Running"))
+            .body(
+                gettext("A server from an earlier session is still running on")
+                    + " " + &record.binding.server_ip + ":" + &record.binding.server_port.to_string() + ". "
+                    + &gettext("Keep using it, or stop it to free the port?"),
+            )
+            .build();
+        dialog.add_response("stop", &gettext("Stop It"));
+        dialog.add_response("adopt", &gettext("Keep Running"));
+        dialog.set_response_appearance("stop", adw::ResponseAppearance::Destructive);
+        dialog.set_response_appearance("adopt", adw::ResponseAppearance::Suggested);
+        dialog.set_default_response(Some("adopt"));
+        dialog.set_close_response("adopt");
+
+        dialog.connect_response(None, glib::clone!(
+            #[weak(rename_to = app)]
+            self,
+            move |_, response| {
+                if response != "stop" {
+                    app.adopt_server(record.clone());
+                    return;
+                }
+
+                let pid = record.pid;
+                let app = app.downgrade();
+                glib::MainContext::default().spawn_local(async move {
+                    let reason = gio::spawn_blocking(move || audioshare::terminate_pid(pid))
+                        .await
+                        .unwrap_or(audioshare::ProcessStopReason::FailedToKill);
+                    info!("Old as-cmd {} stopped: {:?}", pid, reason);
+
+                    if reason == audioshare::ProcessStopReason::FailedToKill {
+                        if let Some(app) = app.upgrade() {
+                            app.on_server_error(&reason);
+                        }
+                    } else {
+                        pidfile::remove();
+                    }
+                });
+            }
+        ));
+
+        dialog.present(Some(&win));
+    }
+
+    fn adopt_server(&self, record: pidfile::PidRecord) {
+        if let Some(win) = self.main_window() {
+            // Show what it serves. Nothing is running yet, so this doesn't
+            // trigger a restart.
+            win.imp().server_ip_entry.set_text(&record.binding.server_ip);
+            win.imp().server_port_entry.set_text(&record.binding.server_port.to_string());
+
+            // Before the lists are in, on_audio_devices_changed does this
+            if let Some(devices) = self.imp().audio_devices.get() {
+                if let Some(position) = devices.endpoint_position_by_id(record.binding.endpoint_id) {
+                    win.imp().audio_endpoint_dropdown.set_selected(position);
+                }
+                if let Some(position) = devices.encoding_position_by_key(&record.binding.encoding_key) {
+                    win.imp().audio_encoding_dropdown.set_selected(position);
+                }
+            }
         }
+
+        self.imp().audio_share_server_thread.get().unwrap().borrow().adopt(record);
+    }
+
+    // The settings the server would be started with right now
+    fn selected_binding(&self) -> Option<audioshare::ServerBinding> {
+        let win = self.main_window()?;
+        let config = win.imp().config.get()?.borrow().clone();
+
+        let ip_text = win.imp().server_ip_entry.text();
+        let server_ip = if ip_text.is_empty() { config.server_ip } else { ip_text.to_string() };
+
+        let port_text = win.imp().server_port_entry.text();
+        let server_port = if port_text.is_empty() {
+            config.server_port
+        } else {
+            configfile::validate_server_port(&port_text).ok()?
+        };
+
+        let endpoint_name = Self::get_selected_string_from_dropdown(&win.imp().audio_endpoint_dropdown)?;
+        let encoding_name = Self::get_selected_string_from_dropdown(&win.imp().audio_encoding_dropdown)?;
+        let devices = self.imp().audio_devices.get()?;
+
+        Some(audioshare::ServerBinding {
+            server_ip,
+            server_port,
+            endpoint_id: devices.endpoint_id(&endpoint_name)?,
+            encoding_key: devices.encoding_key(&encoding_name)?,
+        })
+    }
+
+    // The server stopped without being asked to. Start it again unless it
+    // keeps crashing.
+    fn on_server_exited_unexpectedly(&self, reason: &audioshare::ProcessStopReason) {
+        let Some(win) = self.main_window() else {
+            return;
+        };
+        let Some((restart_on_failure, max_attempts)) = win
+            .imp()
+            .config
+            .get()
+            .map(|config| {
+                let config = config.borrow();
+                (config.restart_on_failure, config.restart_attempts)
+            })
+        else {
+            return;
+        };
+
+        // A server that ran for a while before crashing starts a fresh count
+        let ran_long = self
+            .imp()
+            .server_started_at
+            .get()
+            .is_some_and(|started_at| started_at.elapsed() > STABLE_RUN_TIME);
+        if ran_long {
+            self.imp().restart_attempts.set(0);
+        }
+
+        let attempts = self.imp().restart_attempts.get();
+        if !restart_on_failure || attempts >= max_attempts {
+            warn!("The server stopped unexpectedly, not restarting");
+            self.imp().restart_attempts.set(0);
+            self.on_server_error(reason);
+            return;
+        }
+
+        if !self.imp().audio_share_server_thread.get().unwrap().borrow().begin_restart() {
+            return;
+        }
+
+        self.imp().restart_attempts.set(attempts + 1);
+        info!("The server stopped unexpectedly, restarting ({}/{})", attempts + 1, max_attempts);
+
+        glib::timeout_add_local_once(RESTART_DELAY, glib::clone!(
+            #[weak(rename_to = app)]
+            self,
+            move || {
+                // The user may have stopped it in the meantime
+                if app.server_state() == audioshare::ServerState::Restarting {
+                    if let Err(e) = app.start_server() {
+                        app.on_start_error(e);
+                    }
+                }
+            }
+        ));
     }
 
     // Reset settings to default
     fn action_reset_server_settings(&self) {
-        let server_thread = self.imp().audio_share_server_thread.get().unwrap().borrow();
-
-        if server_thread.is_running() {
-            self.action_stop_server(audioshare::ProcessStopReason::Resetting);
+        if self.server_state().is_active() {
+            self.stop_server();
         }
 
-        println!("Resetting Settings");
+        info!("Resetting Settings");
 
         if let Some(win) = self.main_window() {
             if let Some(config_data) = win.imp().config.get() {
@@ -957,16 +2148,16 @@ impl AudiosharegtkApplication {
                 let audio_endpoint = &config.audio_endpoint;
                 let audio_encoding = &config.audio_encoding;
 
-                let pos: u32 = audioshare::get_endpoint_position_in_dropdown(&audio_endpoint);
-                win.imp().audio_endpoint_dropdown.set_selected(pos.into());
-
-                let encoding_pos: u32 =
-                    audioshare::get_encoding_position_in_dropdown(&audio_encoding);
-                win.imp()
-                    .audio_encoding_dropdown
-                    .set_selected(encoding_pos.into());
+                if let Some(devices) = self.imp().audio_devices.get() {
+                    if let Some(pos) = devices.endpoint_position(audio_endpoint) {
+                        win.imp().audio_endpoint_dropdown.set_selected(pos);
+                    }
+                    if let Some(pos) = devices.encoding_position(audio_encoding) {
+                        win.imp().audio_encoding_dropdown.set_selected(pos);
+                    }
+                }
 
-                if self.is_server_active() == true {
+                if self.server_state().is_active() {
                     win.imp().server_ip_entry.set_text(server_ip);
                     win.imp()
                         .server_port_entry
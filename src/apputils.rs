@@ -1,8 +1,13 @@
-use gtk::{gio};
+use gtk::{gio, glib};
 
 use adw::prelude::*;
 
-const APP_ID:&str = "com.subrighteous.audiosharegtk";
+// Notification ids, so unrelated notifications don't replace each other.
+// Device notifications use their own per-device ids.
+pub const SERVER_ERROR_ID: &str = "server-error";
+pub const SERVER_START_ID: &str = "server-start";
+pub const FIREWALL_TEST_ID: &str = "firewall-test";
+pub const ATTENTION_ID: &str = "attention";
 
 // pub fn show_info_notification<App: IsA<gio::Application>>(window: &App, title: &str, message: &str){
 //     let notification = gio::Notification::new("audio_share_info");
@@ -18,8 +23,32 @@ const APP_ID:&str = "com.subrighteous.audiosharegtk";
 //     window.send_notification(Some(APP_ID), &notification);
 // }
 
-pub fn show_connection_notification<App: IsA<gio::Application>>(window: &App, title: &str, message: &str, connected: &bool){
-    let notification = gio::Notification::new("audio_share_info");
+// A notification button that activates an app action, e.g. "app.stop_server"
+#[derive(Debug, Clone)]
+pub struct NotificationButton {
+    pub label: String,
+    pub action: &'static str,
+    pub target: Option<glib::Variant>,
+}
+
+impl NotificationButton {
+    pub fn new(label: String, action: &'static str) -> Self {
+        Self { label, action, target: None }
+    }
+
+    pub fn with_target(label: String, action: &'static str, target: glib::Variant) -> Self {
+        Self { label, action, target: Some(target) }
+    }
+}
+
+fn add_buttons(notification: &gio::Notification, buttons: &[NotificationButton]) {
+    for button in buttons {
+        notification.add_button_with_target_value(&button.label, button.action, button.target.as_ref());
+    }
+}
+
+pub fn show_connection_notification<App: IsA<gio::Application>>(window: &App, id: &str, title: &str, message: &str, connected: &bool, buttons: &[NotificationButton]){
+    let notification = gio::Notification::new(title);
     // notification.set_icon(&gio::ThemedIcon::new(
     //     APP_ID,
     // ));
@@ -33,13 +62,14 @@ pub fn show_connection_notification<App: IsA<gio::Application>>(window: &App, ti
         let icon = gio::ThemedIcon::new("network-disconnect");
         notification.set_icon(&icon);
     }
+    add_buttons(&notification, buttons);
 
 
-    window.send_notification(Some(APP_ID), &notification);
+    window.send_notification(Some(id), &notification);
 }
 
-pub fn show_error_notification<App: IsA<gio::Application>>(window: &App, title: &str, message: &str){
-    let notification = gio::Notification::new("audio_share_error");
+pub fn show_error_notification<App: IsA<gio::Application>>(window: &App, id: &str, title: &str, message: &str, buttons: &[NotificationButton]){
+    let notification = gio::Notification::new(title);
     // notification.set_icon(&gio::ThemedIcon::new(
     //     APP_ID,
     // ));
@@ -49,8 +79,20 @@ pub fn show_error_notification<App: IsA<gio::Application>>(window: &App, title:
 
     let icon = gio::ThemedIcon::new("action-unavailable-symbolic");
     notification.set_icon(&icon);
+    add_buttons(&notification, buttons);
+
+    window.send_notification(Some(id), &notification);
+}
+
+// The closest GTK 4 gets to an urgency hint. Low priority notifications go
+// to the message tray without a banner, so nothing pops up over a full
+// screen game, and clicking one brings the window back.
+pub fn show_attention_notification<App: IsA<gio::Application>>(window: &App, message: &str){
+    let notification = gio::Notification::new("AudioShareGtk");
+    notification.set_body(Some(message));
+    notification.set_priority(gio::NotificationPriority::Low);
 
-    window.send_notification(Some(APP_ID), &notification);
+    window.send_notification(Some(ATTENTION_ID), &notification);
 }
 
 pub fn show_alert_dialog<App: IsA<gtk::Widget>>(window: &App, title: &str, message: &str){
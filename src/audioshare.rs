@@ -1,14 +1,104 @@
 use get_if_addrs::get_if_addrs;
 
-use std::io::{BufRead, BufReader};
-use std::process::{Child, Command, Stdio};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::net::TcpListener;
-use std::thread;
-use std::time::{Duration, Instant};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime};
 
+use log::{debug, error, info, log, warn, Level};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
 use tokio::sync::watch;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinSet;
+
+use crate::discovery::ServiceAdvertiser;
+use crate::history::SessionTracker;
+use crate::pidfile::{self, PidRecord};
+
+// Log target for everything as-cmd prints
+pub const AS_CMD_TARGET: &str = "audiosharegtk::as_cmd";
+
+// as-cmd tags its lines like "[info] accept ...", map that onto log levels
+pub fn as_cmd_level(line: &str, fallback: Level) -> Level {
+    if line.contains("[error]") || line.contains("[critical]") {
+        Level::Error
+    } else if line.contains("[warning]") || line.contains("[warn]") {
+        Level::Warn
+    } else if line.contains("[info]") {
+        Level::Info
+    } else if line.contains("[debug]") || line.contains("[trace]") {
+        Level::Debug
+    } else {
+        fallback
+    }
+}
+
+// How many as-cmd output lines are kept for the log viewer
+pub const LOG_BUFFER_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+// A line as-cmd printed while the server was running
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub time: SystemTime,
+    pub stream: LogStream,
+    pub level: Level,
+    pub text: String,
+}
+
+// Ring buffer of the most recent as-cmd output
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<LogLine>>>,
+    notifier: broadcast::Sender<LogLine>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel::<LogLine>(64);
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(LOG_BUFFER_LINES))),
+            notifier: tx,
+        }
+    }
+
+    pub fn push(&self, stream: LogStream, level: Level, text: String) {
+        let line = LogLine {
+            time: SystemTime::now(),
+            stream,
+            level,
+            text,
+        };
+
+        {
+            let mut lines = self.lines.lock().unwrap();
+            if lines.len() == LOG_BUFFER_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.clone());
+        }
+
+        let _ = self.notifier.send(line);
+    }
+
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogLine> {
+        self.notifier.subscribe()
+    }
+}
 
 pub fn get_local_ipv4() -> String {
     if let Ok(interfaces) = get_if_addrs() {
@@ -24,11 +114,29 @@ pub fn get_local_ipv4() -> String {
     "8.8.8.8".to_string()
 }
 
+// Every non-loopback IPv4 interface address as (interface name, ip).
+// IPv6 ones are left out, link-local ones can't be bound without a scope.
+pub fn get_interface_addresses() -> Vec<(String, String)> {
+    get_if_addrs()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter(|iface| !iface.is_loopback() && iface.ip().is_ipv4())
+                .map(|iface| (iface.name.clone(), iface.ip().to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Runs as-cmd and blocks, use AudioDeviceCache on the main thread
 pub fn get_audio_endpoints() -> Vec<(bool, u16, String)> {
-    let output = Command::new("/app/bin/as-cmd")
-        .arg("--list-endpoint")
-        .output()
-        .expect("Failed to launch as-cmd");
+    let output = match Command::new("/app/bin/as-cmd").arg("--list-endpoint").output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Failed to launch as-cmd: {}", e);
+            return Vec::new();
+        }
+    };
 
     let command_output = String::from_utf8_lossy(&output.stdout);
 
@@ -64,65 +172,15 @@ pub fn get_audio_endpoints() -> Vec<(bool, u16, String)> {
         .collect()
 }
 
-pub fn get_default_endpoint() -> Option<(bool, u16, String)> {
-    get_audio_endpoints()
-        .into_iter()
-        .find(|(is_default, _, _)| *is_default)
-
-    // Example of usage
-    // if let Some((_, id, name)) = get_default_endpoint(input) {
-    //     println!("Default endpoint -> id: {}, name: {}", id, name);
-    // } else {
-    //     println!("No default endpoint found");
-    // }
-}
-
-pub fn get_endpoint_id(_name: &String) -> Option<u32> {
-    get_audio_endpoints()
-        .into_iter()
-        .find(|(_, _, name)| name == _name)
-        .map(|(_, id, _)| id as u32)
-}
-
-pub fn get_encoding_key(_name: &String) -> Option<String> {
-    get_audio_encoding()
-        .into_iter()
-        .find(|(_, desc)| desc == _name)
-        .map(|(key, _)| key as String)
-}
-
-pub fn get_default_encoding() -> Option<(String, String)> {
-    get_audio_encoding()
-        .into_iter()
-        .find(|(name, _)| name == "default")
-}
-
-pub fn get_endpoint_position_in_dropdown(_name: &str) -> u32 {
-    match get_audio_endpoints()
-        .iter()
-        .position(|(_, _, endpoint_name)| endpoint_name == _name)
-    {
-        Some(idx) => idx as u32,
-        None => {
-            eprintln!("Warning : {} could not be found. Returning 0", _name);
-            0
-        }
-    }
-}
-
-pub fn get_encoding_position_in_dropdown(_name: &String) -> u32 {
-    get_audio_encoding()
-        .iter()
-        .position(|&(_, ref name)| name == _name)
-        .map(|idx| idx as u32)
-        .expect("Couldn't find encoding in Vec")
-}
-
+// Runs as-cmd and blocks, use AudioDeviceCache on the main thread
 pub fn get_audio_encoding() -> Vec<(String, String)> {
-    let output = Command::new("/app/bin/as-cmd")
-        .arg("--list-encoding")
-        .output()
-        .expect("Failed to launch as-cmd");
+    let output = match Command::new("/app/bin/as-cmd").arg("--list-encoding").output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Failed to launch as-cmd: {}", e);
+            return Vec::new();
+        }
+    };
 
     let command_output = String::from_utf8_lossy(&output.stdout);
 
@@ -136,7 +194,7 @@ pub fn get_audio_encoding() -> Vec<(String, String)> {
             let value = parts.next()?.trim();
 
             if key.is_empty() || value.is_empty() {
-                println!("Skipping line: {:?}", line);
+                debug!("Skipping line: {:?}", line);
                 None
             } else {
                 Some((key.to_string(), value.to_string()))
@@ -145,115 +203,350 @@ pub fn get_audio_encoding() -> Vec<(String, String)> {
         .collect()
 }
 
-pub fn get_version() {
-    let output = Command::new("/app/bin/as-cmd")
-        .arg("--version")
-        .output()
-        .expect("Failed to launch as-cmd");
+// The endpoints and encodings as-cmd offers, in the order it lists them, and
+// whether as-cmd can be used at all
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioDevices {
+    pub as_cmd: AsCmdStatus,
+    // (is default, id, name)
+    pub endpoints: Vec<(bool, u16, String)>,
+    // (key, description)
+    pub encodings: Vec<(String, String)>,
+}
+
+impl AudioDevices {
+    pub fn list() -> Self {
+        let as_cmd = check_as_cmd();
+        if as_cmd == AsCmdStatus::Missing {
+            return Self {
+                as_cmd,
+                endpoints: Vec::new(),
+                encodings: Vec::new(),
+            };
+        }
+
+        Self {
+            as_cmd,
+            endpoints: get_audio_endpoints(),
+            encodings: get_audio_encoding(),
+        }
+    }
+
+    // An empty name stands for the default endpoint
+    pub fn endpoint_position(&self, name: &str) -> Option<u32> {
+        self.endpoints
+            .iter()
+            .position(|(is_default, _, endpoint)| if name.is_empty() { *is_default } else { endpoint == name })
+            .map(|position| position as u32)
+    }
+
+    pub fn endpoint_position_by_id(&self, id: u32) -> Option<u32> {
+        self.endpoints
+            .iter()
+            .position(|(_, endpoint_id, _)| *endpoint_id as u32 == id)
+            .map(|position| position as u32)
+    }
+
+    // An empty description stands for the default encoding
+    pub fn encoding_position(&self, description: &str) -> Option<u32> {
+        self.encodings
+            .iter()
+            .position(|(key, name)| if description.is_empty() { key == "default" } else { name == description })
+            .map(|position| position as u32)
+    }
+
+    pub fn encoding_position_by_key(&self, key: &str) -> Option<u32> {
+        self.encodings
+            .iter()
+            .position(|(encoding_key, _)| encoding_key == key)
+            .map(|position| position as u32)
+    }
+
+    pub fn endpoint_id(&self, name: &str) -> Option<u32> {
+        self.endpoints
+            .iter()
+            .find(|(_, _, endpoint)| endpoint == name)
+            .map(|(_, id, _)| *id as u32)
+    }
+
+    pub fn encoding_key(&self, description: &str) -> Option<String> {
+        self.encodings
+            .iter()
+            .find(|(_, name)| name == description)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+// Asking as-cmd for its endpoints and encodings can take seconds when
+// PipeWire is slow, so the lists are kept here and only ever refreshed in
+// the background. Holds None until the first listing is done.
+#[derive(Debug, Clone)]
+pub struct AudioDeviceCache {
+    notifier: watch::Sender<Option<AudioDevices>>,
+    refreshing: Arc<AtomicBool>,
+}
+
+impl Default for AudioDeviceCache {
+    fn default() -> Self {
+        let (tx, _rx) = watch::channel(None);
+        Self {
+            notifier: tx,
+            refreshing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl AudioDeviceCache {
+    pub fn get(&self) -> Option<AudioDevices> {
+        self.notifier.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<AudioDevices>> {
+        self.notifier.subscribe()
+    }
+
+    // List them again, subscribers hear about it when done
+    pub fn refresh(&self) {
+        if self.refreshing.swap(true, Ordering::SeqCst) {
+            debug!("Audio devices are already being listed");
+            return;
+        }
+
+        let cache = self.clone();
+        std::thread::spawn(move || {
+            let devices = AudioDevices::list();
+            debug!("Found {} endpoints and {} encodings", devices.endpoints.len(), devices.encodings.len());
+            cache.refreshing.store(false, Ordering::SeqCst);
+            cache.notifier.send_replace(Some(devices));
+        });
+    }
+}
+
+// Get what as-cmd --version prints, or None when as-cmd can't be run
+pub fn get_version() -> Option<String> {
+    let output = match Command::new("/app/bin/as-cmd").arg("--version").output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Failed to launch as-cmd: {}", e);
+            return None;
+        }
+    };
+
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    info!("as-cmd version: {}", version);
+    debug!("as-cmd --version stderr: {}", String::from_utf8_lossy(&output.stderr).trim());
+
+    Some(version)
+}
+
+// The oldest as-cmd release this app is known to work with
+pub const MIN_AS_CMD_VERSION: AsCmdVersion = AsCmdVersion {
+    major: 0,
+    minor: 3,
+    patch: 0,
+};
+
+// A parsed as-cmd version, e.g. "0.3.4"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AsCmdVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl AsCmdVersion {
+    // Takes the first word that looks like a version, so both "0.3.4" and
+    // "as-cmd v0.3.4" work
+    pub fn parse(output: &str) -> Option<Self> {
+        output.split_whitespace().find_map(|word| {
+            let mut parts = word.trim_start_matches('v').split('.');
+            let major = parts.next()?.parse().ok()?;
+            let minor = parts.next()?.parse().ok()?;
+            // Strip suffixes like "4-beta"
+            let patch = parts
+                .next()
+                .map(|patch| patch.split(|c: char| !c.is_ascii_digit()).next().unwrap_or(""))
+                .and_then(|patch| patch.parse().ok())
+                .unwrap_or(0);
+
+            Some(Self { major, minor, patch })
+        })
+    }
+}
+
+impl std::fmt::Display for AsCmdVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+// Whether the installed as-cmd can be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsCmdStatus {
+    Supported(AsCmdVersion),
+    TooOld(AsCmdVersion),
+    // It runs, but the version could not be read
+    Unknown(String),
+    Missing,
+}
+
+// Run as-cmd --version and compare it with MIN_AS_CMD_VERSION
+pub fn check_as_cmd() -> AsCmdStatus {
+    let Some(output) = get_version() else {
+        return AsCmdStatus::Missing;
+    };
+
+    match AsCmdVersion::parse(&output) {
+        Some(version) if version >= MIN_AS_CMD_VERSION => AsCmdStatus::Supported(version),
+        Some(version) => {
+            warn!("as-cmd {} is older than the minimum supported {}", version, MIN_AS_CMD_VERSION);
+            AsCmdStatus::TooOld(version)
+        }
+        None => {
+            warn!("Could not parse the as-cmd version from {:?}", output);
+            AsCmdStatus::Unknown(output)
+        }
+    }
+}
+
+// Audio Share control protocol commands. Every command is sent as a
+// little-endian u32 on the TCP control connection.
+const CMD_GET_FORMAT: u32 = 1;
+const CMD_START_PLAY: u32 = 2;
+
+// How long an accepted connection gets to send its hello
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The outcome of a firewall test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirewallTestResult {
+    // A genuine Audio Share client got through the firewall
+    Passed { client: SocketAddr, elapsed: Duration },
+    // No client completed the handshake before the timeout
+    TimedOut(Duration),
+    // The user stopped the test
+    Cancelled,
+    // The test listener could not bind to the configured address
+    BindFailed,
+}
+
+// Read the first command of a freshly accepted connection and check that it
+// is what an Audio Share client sends right after connecting.
+async fn read_client_hello(mut socket: tokio::net::TcpStream) -> bool {
+    let mut buf = [0u8; 4];
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, socket.read_exact(&mut buf)).await {
+        Ok(Ok(_)) => matches!(u32::from_le_bytes(buf), CMD_GET_FORMAT | CMD_START_PLAY),
+        _ => false,
+    }
+}
+
+// Wait for a client hello until the timeout runs out or the test is cancelled
+async fn run_firewall_test(
+    addr: String,
+    timeout: Duration,
+    mut cancel: oneshot::Receiver<()>,
+) -> FirewallTestResult {
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(_) => return FirewallTestResult::BindFailed,
+    };
+
+    let start = Instant::now();
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    // Handshakes run next to the accept loop so a silent connection
+    // doesn't hold up the next one
+    let mut handshakes = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => return FirewallTestResult::TimedOut(timeout),
+
+            _ = &mut cancel => return FirewallTestResult::Cancelled,
+
+            Ok((socket, client)) = listener.accept() => {
+                handshakes.spawn(async move {
+                    (client, read_client_hello(socket).await)
+                });
+            }
 
-    println!("\nTesting as-cmd\n{}", "----------");
-    println!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-    println!("{}", "----------");
-    println!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-    println!("\n{}\n", "----------");
+            Some(Ok((client, is_client))) = handshakes.join_next() => {
+                // Port scanners and browsers also get through,
+                // only count connections that speak the protocol
+                if is_client {
+                    return FirewallTestResult::Passed {
+                        client,
+                        elapsed: start.elapsed(),
+                    };
+                }
+                debug!("Ignoring connection from {} without a client hello", client);
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct FirewallTestThread {
-    pub server_child: Arc<Mutex<Option<TcpListener>>>,
     pub running: Arc<Mutex<bool>>,
-    pub result_notifier: broadcast::Sender<bool>,
+    pub cancel_notifier: Mutex<Option<oneshot::Sender<()>>>,
+    pub result_notifier: broadcast::Sender<FirewallTestResult>,
 }
 
 impl FirewallTestThread{
     pub fn new() -> Self {
-        let (device_tx, _rx) = broadcast::channel::<bool>(16);
+        let (device_tx, _rx) = broadcast::channel::<FirewallTestResult>(16);
         Self {
-            server_child: Arc::new(Mutex::new(None)),
             running: Arc::new(Mutex::new(false)),
+            cancel_notifier: Mutex::new(None),
             result_notifier: device_tx,
         }
     }
 
-    pub fn subscribe_result_event(&self) -> broadcast::Receiver<bool>{
+    pub fn subscribe_result_event(&self) -> broadcast::Receiver<FirewallTestResult>{
         self.result_notifier.subscribe()
     }
 
-    pub fn start(&self,server_ip: String, server_port: u16,){
-        let server_child = self.server_child.clone();
-        {
-            let guard = server_child.lock().unwrap();
-            if guard.is_some() {
-                eprintln!("Test already running");
-                return;
-            }
-        }
+    pub fn start(&self, server_ip: String, server_port: u16, timeout: Duration){
         let running_guard = self.running.clone();
 
-        let result_notifier = self.result_notifier.clone();
-
         {
             // check if already running
-            let guard = server_child.lock().unwrap();
-            if guard.is_some() || *running_guard.lock().unwrap() {
-                eprint!("Test already running");
+            let mut running = running_guard.lock().unwrap();
+            if *running {
+                warn!("Test already running");
                 return;
             }
+            *running = true;
         }
 
-        *running_guard.lock().unwrap() = true;
+        let result_notifier = self.result_notifier.clone();
+
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        *self.cancel_notifier.lock().unwrap() = Some(cancel_tx);
 
         std::thread::spawn(move || {
             let addr = format!("{}:{}", server_ip, server_port);
 
-            let _result = match TcpListener::bind(&addr) {
-                Ok(listener) => {
-                    listener.set_nonblocking(true).unwrap();
-                    let start = Instant::now();
-                    let timeout = Duration::from_secs(9);
-
-                    let _guard = server_child.lock().unwrap();
-                    //*guard = Some(listener);
-
-                    let mut success = false;
-                    while start.elapsed() < timeout && *running_guard.lock().unwrap(){
-                        if let Ok((_socket, _addr)) = listener.accept() {
-                            success = true;
-                            break;
-                        }
-                        thread::sleep(Duration::from_millis(50)); // avoid busy loop
-                    }
-
-                    // Only notify if the system timer went out
-                    if *running_guard.lock().unwrap(){
-                        let _ = result_notifier.send(success);
-                    }
-
-                    success
-                }
-                Err(_) => {
-                    let _ = result_notifier.send(false);
-                    false // failed to bind
+            let result = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime.block_on(run_firewall_test(addr, timeout, cancel_rx)),
+                Err(e) => {
+                    error!("Failed to start the firewall test runtime: {}", e);
+                    FirewallTestResult::BindFailed
                 }
             };
 
-
-
+            *running_guard.lock().unwrap() = false;
+            let _ = result_notifier.send(result);
         });
     }
 
     pub fn stop(&self) {
-       // Set running to false first so the loop sees it
-        *self.running.lock().unwrap() = false;
-
-        // Take the listener out of the Arc<Mutex<>> so the loop can’t access it anymore
-        self.server_child.lock().unwrap().take();
-
-        println!("Firewall test stopped");
-
-        //*guard = None;
-        //*running_guard = false;
-        //println!("Testing is stopped");
+        // Wake the test up, it reports back as cancelled
+        if let Some(cancel) = self.cancel_notifier.lock().unwrap().take() {
+            let _ = cancel.send(());
+            info!("Firewall test stopped");
+        }
     }
 
     pub fn is_running(&self) -> bool {
@@ -261,210 +554,682 @@ impl FirewallTestThread{
     }
 }
 
-// A message to send when the process stops
+// Why the process stopped
 #[derive(Debug , Clone, PartialEq, Eq)]
 pub enum ProcessStopReason {
     InvalidBinding,
     InvalidArgument,
     FirewallBlocked,
+    // Exited within the grace period after SIGTERM
     ExitedSuccessfully,
-    Resetting,
+    // Ignored SIGTERM and had to be killed
+    Killed,
     ExitedWithError(Option<i32>),
+    // Still running after SIGKILL, and probably still holding the port
     FailedToKill,
+    // as-cmd could not be launched at all, start() returns the error
+    FailedToStart,
+}
+
+// How long as-cmd gets to close its client sockets before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Child::wait with a time limit
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Ask the process to exit with SIGTERM and only SIGKILL it when it doesn't.
+// The child is always reaped, so it can't linger as a zombie.
+fn terminate(child: &mut Child) -> ProcessStopReason {
+    if let Ok(Some(status)) = child.try_wait() {
+        debug!("as-cmd had already exited with {}", status);
+        return ProcessStopReason::ExitedSuccessfully;
+    }
+
+    // SAFETY: kill() has no memory safety requirements, and the pid is our
+    // own child which has not been reaped yet, so it can't have been reused
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+        warn!("Failed to send SIGTERM to as-cmd: {}", io::Error::last_os_error());
+    }
+
+    match wait_timeout(child, STOP_TIMEOUT) {
+        Ok(Some(status)) => {
+            info!("as-cmd exited with {}", status);
+            return ProcessStopReason::ExitedSuccessfully;
+        }
+        Ok(None) => warn!("as-cmd did not exit within {:?}, killing it", STOP_TIMEOUT),
+        Err(e) => error!("Failed to wait for as-cmd: {}", e),
+    }
+
+    if let Err(e) = child.kill() {
+        error!("Failed to kill as-cmd: {}", e);
+        return ProcessStopReason::FailedToKill;
+    }
+
+    match wait_timeout(child, STOP_TIMEOUT) {
+        Ok(Some(_)) => ProcessStopReason::Killed,
+        Ok(None) => {
+            error!("as-cmd is still running after SIGKILL");
+            ProcessStopReason::FailedToKill
+        }
+        Err(e) => {
+            error!("Failed to wait for as-cmd: {}", e);
+            ProcessStopReason::FailedToKill
+        }
+    }
+}
+
+// Wait for a process that isn't our child to go away
+fn wait_gone(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while pidfile::is_as_cmd(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    true
 }
 
+// Like terminate(), for an as-cmd left behind by an earlier session
+pub fn terminate_pid(pid: u32) -> ProcessStopReason {
+    if !pidfile::is_as_cmd(pid) {
+        return ProcessStopReason::ExitedSuccessfully;
+    }
+
+    // SAFETY: kill() has no memory safety requirements. The pid was checked
+    // to be as-cmd just before.
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        warn!("Failed to send SIGTERM to as-cmd {}: {}", pid, io::Error::last_os_error());
+    }
+
+    if wait_gone(pid, STOP_TIMEOUT) {
+        info!("as-cmd {} exited", pid);
+        return ProcessStopReason::ExitedSuccessfully;
+    }
+    warn!("as-cmd {} did not exit within {:?}, killing it", pid, STOP_TIMEOUT);
+
+    // SAFETY: as above
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+        error!("Failed to kill as-cmd {}: {}", pid, io::Error::last_os_error());
+        return ProcessStopReason::FailedToKill;
+    }
+
+    if wait_gone(pid, STOP_TIMEOUT) {
+        ProcessStopReason::Killed
+    } else {
+        error!("as-cmd {} is still running after SIGKILL", pid);
+        ProcessStopReason::FailedToKill
+    }
+}
+
+
+// as-cmd's --bind argument. IPv6 addresses need brackets to keep the port
+// apart.
+fn bind_arg(binding: &ServerBinding) -> String {
+    match binding.server_ip.parse::<IpAddr>() {
+        Ok(ip) => format!("--bind={}", SocketAddr::new(ip, binding.server_port)),
+        Err(_) => format!("--bind={}:{}", binding.server_ip, binding.server_port),
+    }
+}
+
+// The address and stream settings a server was started with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerBinding {
+    pub server_ip: String,
+    pub server_port: u16,
+    pub endpoint_id: u32,
+    pub encoding_key: String,
+}
 
 // AudioShare Thread
-#[derive(Debug)]
+// Live numbers about the running server, kept up to date from the as-cmd
+// output. as-cmd 0.3.x does not log how much it sends, so there are no
+// traffic counters and the window says so instead.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub started_at: Option<Instant>,
+    pub encoding_key: Option<String>,
+    // ip:port of every connected client
+    pub clients: Vec<String>,
+}
+
+impl ServerStats {
+    pub fn uptime(&self) -> Option<Duration> {
+        self.started_at.map(|started_at| started_at.elapsed())
+    }
+}
+
+// The client address at the end of an "[info] accept" or "[info] close" line
+fn client_address(line: &str) -> Option<&str> {
+    line.split_whitespace().last()
+}
+
+// The IP of a client address like "192.168.1.5:50412", "[fd00::5]:50412"
+// or the unbracketed "::ffff:192.168.1.5:50412"
+pub fn client_ip(address: &str) -> Option<IpAddr> {
+    if let Ok(address) = address.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+    address
+        .rsplit_once(':')
+        .and_then(|(ip, _port)| ip.trim_start_matches('[').trim_end_matches(']').parse().ok())
+}
+
+// Where the server is in its lifecycle. Only the server thread moves it
+// along, the UI renders whatever it is told.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ServerState {
+    #[default]
+    Stopped,
+    Starting,
+    Running,
+    Stopping,
+    // Coming back after a crash or with new settings
+    Restarting,
+    Failed(ProcessStopReason),
+}
+
+impl ServerState {
+    // Whether the server is on as far as the user is concerned
+    pub fn is_active(&self) -> bool {
+        matches!(self, ServerState::Starting | ServerState::Running | ServerState::Restarting)
+    }
+
+    // Halfway between two states, nothing should be started or stopped
+    pub fn is_busy(&self) -> bool {
+        matches!(self, ServerState::Starting | ServerState::Stopping)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct AudioShareServerThread {
     pub server_child: Arc<Mutex<Option<Child>>>,
-    pub running: Arc<Mutex<bool>>,
-    pub process_stop_notifier: watch::Sender<Option<ProcessStopReason>>,
+    // Bumped for every as-cmd process, so the reader threads of a replaced
+    // or stopped process leave the shared state alone
+    pub generation: Arc<AtomicU64>,
+    // An as-cmd from an earlier session the user chose to keep, see adopt()
+    pub adopted_pid: Arc<Mutex<Option<u32>>>,
+    pub binding: Arc<Mutex<Option<ServerBinding>>>,
+    // Why as-cmd last went down, kept after the state moves on so a bug
+    // report filed after a restart still has it
+    pub last_stop_reason: Arc<Mutex<Option<ProcessStopReason>>>,
+    pub advertiser: Arc<Mutex<ServiceAdvertiser>>,
+    pub log_buffer: LogBuffer,
+    pub state_notifier: watch::Sender<ServerState>,
     pub device_connected_notifier: broadcast::Sender<(String, bool)>,
+    pub stats_notifier: watch::Sender<ServerStats>,
+    pub history: SessionTracker,
 }
 
 impl AudioShareServerThread {
     pub fn new() -> Self {
-        let (tx, _rx) = watch::channel(None);
+        let (state_tx, _rx) = watch::channel(ServerState::default());
         let (device_tx, _rx) = broadcast::channel::<(String, bool)>(16);
+        let (stats_tx, _rx) = watch::channel(ServerStats::default());
         Self {
             server_child: Arc::new(Mutex::new(None)),
-            running: Arc::new(Mutex::new(false)),
-            process_stop_notifier: tx,
+            generation: Arc::new(AtomicU64::new(0)),
+            adopted_pid: Arc::new(Mutex::new(None)),
+            binding: Arc::new(Mutex::new(None)),
+            last_stop_reason: Arc::new(Mutex::new(None)),
+            advertiser: Arc::new(Mutex::new(ServiceAdvertiser::new())),
+            log_buffer: LogBuffer::new(),
+            state_notifier: state_tx,
             device_connected_notifier: device_tx,
+            stats_notifier: stats_tx,
+            history: SessionTracker::new(),
         }
     }
 
-    pub fn subscribe_stop_event(&self) -> watch::Receiver<Option<ProcessStopReason>> {
-        self.process_stop_notifier.subscribe()
+    pub fn subscribe_state(&self) -> watch::Receiver<ServerState> {
+        self.state_notifier.subscribe()
     }
 
     pub fn subscribe_device_event(&self) -> broadcast::Receiver<(String, bool)>{
         self.device_connected_notifier.subscribe()
     }
 
+    pub fn subscribe_stats(&self) -> watch::Receiver<ServerStats> {
+        self.stats_notifier.subscribe()
+    }
+
+    pub fn state(&self) -> ServerState {
+        self.state_notifier.borrow().clone()
+    }
+
+    pub fn last_stop_reason(&self) -> Option<ProcessStopReason> {
+        self.last_stop_reason.lock().unwrap().clone()
+    }
+
+    // Only called with the child lock held, so transitions can't interleave.
+    // The adopted pid is covered by the same lock.
+    fn set_state(&self, state: ServerState) {
+        debug!("Server state : {:?}", state);
+        if let ServerState::Failed(reason) = &state {
+            *self.last_stop_reason.lock().unwrap() = Some(reason.clone());
+        }
+        self.state_notifier.send_replace(state);
+    }
+
     pub fn start(
         &self,
         server_ip: String,
         server_port: u16,
         endpoint_id: u32,
         encoding_key: String,
-    ) {
+    ) -> io::Result<()> {
         let mut guard = self.server_child.lock().unwrap();
-        let mut running_guard = self.running.lock().unwrap();
 
-        if *running_guard {
-            eprint!("Command already running");
-            return;
+        if guard.is_some()
+            || self.adopted_pid.lock().unwrap().is_some()
+            || matches!(self.state(), ServerState::Starting | ServerState::Running | ServerState::Stopping)
+        {
+            warn!("Command already running");
+            return Ok(());
         }
 
-        if guard.is_some() {
-            eprintln!("Command already running");
-            return;
-        }
+        self.spawn(
+            &mut guard,
+            ServerBinding {
+                server_ip,
+                server_port,
+                endpoint_id,
+                encoding_key,
+            },
+        )
+    }
 
-        println!("Starting server thread with server ip : {server_ip} server port : {server_port} endpoint ID: {endpoint_id}, encoding key: {encoding_key}");
+    // Launch as-cmd and the threads reading its output. Must run on the main
+    // thread, see the pre_exec hook.
+    fn spawn(&self, guard: &mut Option<Child>, binding: ServerBinding) -> io::Result<()> {
+        self.set_state(ServerState::Starting);
 
-        let binding_arg: String = format!("--bind={}:{}", &server_ip, &server_port.to_string());
-        println!("{}", &binding_arg.to_string());
+        info!(
+            "Starting server thread with server ip : {} server port : {} endpoint ID: {}, encoding key: {}",
+            binding.server_ip, binding.server_port, binding.endpoint_id, binding.encoding_key
+        );
+
+        let binding_arg: String = bind_arg(&binding);
+        debug!("{}", &binding_arg);
 
         // Build the command using passed-in variables
-        let cmd = Command::new("/app/bin/as-cmd")
+        let mut command = Command::new("/app/bin/as-cmd");
+        command
             .arg(binding_arg)
             .arg("-e")
-            .arg(&endpoint_id.to_string())
+            .arg(&binding.endpoint_id.to_string())
             .arg("--encoding")
-            .arg(&encoding_key)
+            .arg(&binding.encoding_key)
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn();
-
-        match cmd {
-            Ok(mut child) => {
-                // Spawn a new thread to read the child process's stdout
-                let child_stdout = child.stderr.take().unwrap();
-                let child_stdinfo = child.stdout.take().unwrap();
-                let child_id = self.server_child.clone();
-                let running_flag = self.running.clone();
-                let running_flag_stdout = self.running.clone();
-
-                let stop_notifier = self.process_stop_notifier.clone();
-                let device_connected_notifier = self.device_connected_notifier.clone();
-
-                *guard = Some(child);
-
-                // Thread for stdout
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(child_stdinfo);
-                    for line in reader.lines().flatten() {
-                        println!("[AS-CMD Out]: {}", line);
-                        if line.contains("[info] accept"){
-                            // Split by spaces and take the last part
-                            if let Some(last) = line.split_whitespace().last() {
-                                // Split by ':' to separate IP and port
-                                if let Some((ip, _port)) = last.split_once(':') {
-                                    let _ = device_connected_notifier.send((ip.to_string(), true));
-                                }
-                            }
+            .stderr(Stdio::piped());
+
+        // Take as-cmd down with the app, even when the app crashes or is
+        // killed, so it doesn't keep holding the port. The kernel sends the
+        // signal when the spawning thread exits rather than the process,
+        // which is why the server is only started from the main thread.
+        let parent = std::process::id();
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // The app may have died before the signal was set up
+                if libc::getppid() as u32 != parent {
+                    return Err(io::Error::from_raw_os_error(libc::ESRCH));
+                }
+                Ok(())
+            });
+        }
+
+        let cmd = command.spawn();
+
+        let mut child = match cmd {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Failed to start command: {}", e);
+                // A restart leaves the old binding and advertisement behind
+                self.clear();
+                self.set_state(ServerState::Failed(ProcessStopReason::FailedToStart));
+                return Err(e);
+            }
+        };
+
+        let child_stderr = child.stderr.take().unwrap();
+        let child_stdout = child.stdout.take().unwrap();
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let endpoint_id = binding.endpoint_id;
+        let session_encoding = binding.encoding_key.clone();
+
+        let record = PidRecord {
+            pid: child.id(),
+            binding: binding.clone(),
+        };
+        if let Err(e) = pidfile::save(&record) {
+            warn!("Failed to write the pid file: {}", e);
+        }
+
+        *guard = Some(child);
+
+        self.stats_notifier.send_replace(ServerStats {
+            started_at: Some(Instant::now()),
+            encoding_key: Some(binding.encoding_key.clone()),
+            clients: Vec::new(),
+        });
+
+        if let Err(e) = self.advertiser.lock().unwrap().advertise(&binding) {
+            error!("Failed to advertise the server: {}", e);
+        }
+        *self.binding.lock().unwrap() = Some(binding);
 
+        // Thread for stdout
+        let server = self.clone();
+        std::thread::spawn(move || {
+            let reader = BufReader::new(child_stdout);
+            for line in reader.lines().flatten() {
+                let level = as_cmd_level(&line, Level::Info);
+                log!(target: AS_CMD_TARGET, level, "{}", line);
+                server.log_buffer.push(LogStream::Stdout, level, line.clone());
+                if line.contains("[info] accept"){
+                    // Split by spaces and take the last part
+                    if let Some(last) = client_address(&line) {
+                        server.stats_notifier.send_modify(|stats| stats.clients.push(last.to_string()));
+                        server.history.opened(last, endpoint_id, &session_encoding);
+                        if let Some(ip) = client_ip(last) {
+                            let _ = server.device_connected_notifier.send((ip.to_string(), true));
                         }
+                    }
 
-                        if line.contains("[info] close"){
-                            // Split by spaces and take the last part
-                            if let Some(last) = line.split_whitespace().last() {
-                                // Split by ':' to separate IP and port
-                                if let Some((ip, _port)) = last.split_once(':') {
-                                    let _ = device_connected_notifier.send((ip.to_string(), false));
-                                }
-                            }
+                }
 
+                if line.contains("[info] close"){
+                    // Split by spaces and take the last part
+                    if let Some(last) = client_address(&line) {
+                        server.stats_notifier.send_modify(|stats| stats.clients.retain(|client| client != last));
+                        server.history.closed(last);
+                        if let Some(ip) = client_ip(last) {
+                            let _ = server.device_connected_notifier.send((ip.to_string(), false));
                         }
                     }
-                    *running_flag_stdout.lock().unwrap() = false;
-                });
 
-                // Thread of stderror
-                std::thread::spawn(move || {
-                    let reader = BufReader::new(child_stdout);
+                }
+            }
+        });
 
-                    let mut reason = ProcessStopReason::ExitedSuccessfully;
+        // Thread of stderror
+        let server = self.clone();
+        std::thread::spawn(move || {
+            let reader = BufReader::new(child_stderr);
 
-                    for line in reader.lines().flatten() {
-                        println!("[AS-CMD Error]: {}", line);
-                        // Check for specific logs to stop the process
-                        if line.contains("bind: Cannot assign requested address") {
-                            println!("Detected 'Cannot assign requested address' log. Stopping child process...");
+            let mut reason = ProcessStopReason::ExitedSuccessfully;
 
-                            reason = ProcessStopReason::InvalidBinding;
-                            break;
+            for line in reader.lines().flatten() {
+                let level = as_cmd_level(&line, Level::Warn);
+                log!(target: AS_CMD_TARGET, level, "{}", line);
+                server.log_buffer.push(LogStream::Stderr, level, line.clone());
+                // Check for specific logs to stop the process
+                if line.contains("bind: Cannot assign requested address") {
+                    warn!("Detected 'Cannot assign requested address' log. Stopping child process...");
 
-                        }
-                        if line.contains("Invalid argument"){
-                            reason = ProcessStopReason::InvalidArgument;
-                            break;
-                        }
-                    }
+                    reason = ProcessStopReason::InvalidBinding;
+                    break;
 
-                    let mut child_guard = child_id.lock().unwrap();
-                    if let Some(c) = child_guard.as_mut() {
-                        if let Err(e) = c.kill() {
-                            eprintln!("Failed to kill child process: {}", e);
-                        }
-                    }
+                }
+                if line.contains("Invalid argument"){
+                    reason = ProcessStopReason::InvalidArgument;
+                    break;
+                }
+            }
 
-                    *child_guard = None;
-                    *running_flag.lock().unwrap() = false;
-                    let _ = stop_notifier.send(Some(reason));
-                });
+            let child = {
+                let mut child_guard = server.server_child.lock().unwrap();
 
-                *running_guard = true;
-                println!("Command started");
+                // Stopped or replaced on purpose, nothing to report
+                if server.generation.load(Ordering::SeqCst) != generation {
+                    debug!("as-cmd process {} exited", generation);
+                    return;
+                }
+
+                child_guard.take()
+            };
+
+            if let Some(mut c) = child {
+                // Its output closed, so as-cmd is on its way out by itself.
+                // Nobody asked it to stop, so even a clean exit is a failure.
+                if reason == ProcessStopReason::ExitedSuccessfully {
+                    reason = match wait_timeout(&mut c, STOP_TIMEOUT) {
+                        Ok(Some(status)) => ProcessStopReason::ExitedWithError(status.code()),
+                        _ => ProcessStopReason::ExitedWithError(None),
+                    };
+                }
+
+                if terminate(&mut c) == ProcessStopReason::FailedToKill {
+                    reason = ProcessStopReason::FailedToKill;
+                }
             }
-            Err(e) => {
-                eprintln!("Failed to start command: {}", e);
-                *running_guard = false;
+
+            let _guard = server.server_child.lock().unwrap();
+            // The user stopped it while it was going down
+            if server.generation.load(Ordering::SeqCst) != generation {
+                return;
             }
-        }
+
+            if reason != ProcessStopReason::FailedToKill {
+                pidfile::remove();
+            }
+            server.clear();
+            server.set_state(ServerState::Failed(reason));
+        });
+
+        self.set_state(ServerState::Running);
+        info!("Command started");
+        Ok(())
     }
 
+    // Stop the server in the background, the state reports when it's done
     pub fn stop(&self) {
-        let mut guard = self.server_child.lock().unwrap();
-        let mut running_guard = self.running.lock().unwrap();
+        let server = self.clone();
+        std::thread::spawn(move || {
+            server.stop_and_wait();
+        });
+    }
+
+    // Stop the server and block until as-cmd is gone. Returns None when
+    // there was nothing to stop.
+    pub fn stop_and_wait(&self) -> Option<ProcessStopReason> {
+        let (child, adopted_pid) = {
+            let mut guard = self.server_child.lock().unwrap();
+            let adopted_pid = self.adopted_pid.lock().unwrap().take();
 
-        if let Some(server_child) = guard.as_mut() {
-            match server_child.kill() {
-                Ok(_) => println!("Process killed"),
-                Err(e) => eprintln!("Failed to kill process: {}", e),
+            if guard.is_none() && adopted_pid.is_none() && !self.state().is_active() {
+                return None;
             }
+
+            self.set_state(ServerState::Stopping);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            (guard.take(), adopted_pid)
+        };
+
+        self.clear();
+
+        let reason = match (child, adopted_pid) {
+            (Some(mut server_child), _) => terminate(&mut server_child),
+            (None, Some(pid)) => terminate_pid(pid),
+            (None, None) => ProcessStopReason::ExitedSuccessfully,
+        };
+        info!("Server stopped: {:?}", reason);
+        *self.last_stop_reason.lock().unwrap() = Some(reason.clone());
+
+        let _guard = self.server_child.lock().unwrap();
+        if reason == ProcessStopReason::FailedToKill {
+            self.set_state(ServerState::Failed(reason.clone()));
+        } else {
+            pidfile::remove();
+            self.set_state(ServerState::Stopped);
         }
 
-        *guard = None;
-        *running_guard = false;
+        Some(reason)
     }
 
-    pub fn reset(&self){
-        let mut guard = self.server_child.lock().unwrap();
-        let mut running_guard = self.running.lock().unwrap();
+    // Take the running process down so start() can bring it back with new
+    // settings. The state stays at Restarting meanwhile and the service stays
+    // advertised, so clients only see a short drop. The receiver fires once
+    // the port is free again. Returns None unless the server is running.
+    pub fn reconfigure(&self) -> Option<oneshot::Receiver<()>> {
+        let (old_child, adopted_pid) = {
+            let mut guard = self.server_child.lock().unwrap();
+            if self.state() != ServerState::Running {
+                return None;
+            }
+            self.set_state(ServerState::Restarting);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            (guard.take(), self.adopted_pid.lock().unwrap().take())
+        };
+
+        // as-cmd drops every client when it exits
+        self.history.close_all();
+
+        let (done_tx, done_rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            let reason = match (old_child, adopted_pid) {
+                (Some(mut child), _) => terminate(&mut child),
+                (None, Some(pid)) => terminate_pid(pid),
+                (None, None) => ProcessStopReason::ExitedSuccessfully,
+            };
+            debug!("Old as-cmd process stopped: {:?}", reason);
+            let _ = done_tx.send(());
+        });
+
+        Some(done_rx)
+    }
+
+    // Take over an as-cmd left running by an earlier session. Its output
+    // went to the old app, so there are no client events or statistics,
+    // but it can be stopped and switched like any other server.
+    pub fn adopt(&self, record: PidRecord) {
+        let guard = self.server_child.lock().unwrap();
+        let mut adopted_pid = self.adopted_pid.lock().unwrap();
+
+        if guard.is_some() || adopted_pid.is_some() || self.state().is_active() {
+            warn!("Not adopting as-cmd {}, a server is already running", record.pid);
+            return;
+        }
+
+        info!("Adopting as-cmd {} on {}:{}", record.pid, record.binding.server_ip, record.binding.server_port);
+
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let pid = record.pid;
+        *adopted_pid = Some(pid);
+
+        self.stats_notifier.send_replace(ServerStats {
+            started_at: Some(Instant::now()),
+            encoding_key: Some(record.binding.encoding_key.clone()),
+            clients: Vec::new(),
+        });
+
+        if let Err(e) = self.advertiser.lock().unwrap().advertise(&record.binding) {
+            error!("Failed to advertise the server: {}", e);
+        }
+        *self.binding.lock().unwrap() = Some(record.binding);
+        self.set_state(ServerState::Running);
 
-        if let Some(server_child) = guard.as_mut() {
-            match server_child.kill() {
-                Ok(_) => println!("Process killed"),
-                Err(e) => eprintln!("Failed to kill process: {}", e),
+        // Without its output, the process itself is the only sign of life
+        let server = self.clone();
+        std::thread::spawn(move || {
+            while pidfile::is_as_cmd(pid) {
+                if server.generation.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                std::thread::sleep(Duration::from_secs(1));
+            }
+
+            let _guard = server.server_child.lock().unwrap();
+            if server.generation.load(Ordering::SeqCst) != generation {
+                return;
             }
+
+            server.adopted_pid.lock().unwrap().take();
+            pidfile::remove();
+            server.clear();
+            server.set_state(ServerState::Failed(ProcessStopReason::ExitedWithError(None)));
+        });
+    }
+
+    // Keep a crashed server marked as on until start() brings it back.
+    // Returns false when the user got to it first.
+    pub fn begin_restart(&self) -> bool {
+        let guard = self.server_child.lock().unwrap();
+
+        if guard.is_none() && matches!(self.state(), ServerState::Failed(_)) {
+            self.set_state(ServerState::Restarting);
+            true
+        } else {
+            false
         }
+    }
 
-        let stop_notifier = self.process_stop_notifier.clone();
+    // Forget the process that just went away. The pid file is left to the
+    // callers, it has to stay for as long as the process might be alive.
+    fn clear(&self) {
+        *self.binding.lock().unwrap() = None;
+        self.advertiser.lock().unwrap().withdraw();
+        self.stats_notifier.send_replace(ServerStats::default());
+        self.history.close_all();
+    }
+}
 
-        let reason = ProcessStopReason::Resetting;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::binding;
 
-        let _ = stop_notifier.send(Some(reason));
+    #[test]
+    fn bind_arg_brackets_ipv6() {
+        assert_eq!(bind_arg(&binding("192.168.1.10")), "--bind=192.168.1.10:65530");
+        assert_eq!(bind_arg(&binding("0.0.0.0")), "--bind=0.0.0.0:65530");
+        assert_eq!(bind_arg(&binding("fd00::2")), "--bind=[fd00::2]:65530");
+    }
 
-        *guard = None;
-        *running_guard = false;
+    #[test]
+    fn offers_only_ipv4_interfaces() {
+        for (_, ip) in get_interface_addresses() {
+            assert!(ip.parse::<std::net::Ipv4Addr>().is_ok(), "{} was offered", ip);
+        }
     }
 
-    pub fn is_running(&self) -> bool {
+    #[test]
+    fn splits_the_ip_from_client_addresses() {
+        for (address, ip) in [
+            ("192.168.1.5:50412", "192.168.1.5"),
+            ("[fd00::5]:50412", "fd00::5"),
+            ("[::ffff:192.168.1.5]:50412", "::ffff:192.168.1.5"),
+            ("::ffff:192.168.1.5:50412", "::ffff:192.168.1.5"),
+            ("fe80::1:50412", "fe80::1"),
+        ] {
+            assert_eq!(client_ip(address).map(|ip| ip.to_string()).as_deref(), Some(ip), "{}", address);
+        }
+        assert_eq!(client_ip("50412"), None);
+    }
+
+    #[test]
+    fn keeps_the_last_stop_reason_after_a_restart() {
+        let server = AudioShareServerThread::new();
+        assert_eq!(server.last_stop_reason(), None);
 
-        *self.running.lock().unwrap()
+        server.set_state(ServerState::Failed(ProcessStopReason::InvalidBinding));
+        server.set_state(ServerState::Restarting);
+        server.set_state(ServerState::Running);
+        assert_eq!(server.last_stop_reason(), Some(ProcessStopReason::InvalidBinding));
     }
 }
@@ -1,10 +1,61 @@
 use std::{fs,io};
+use std::collections::HashMap;
+use std::net::IpAddr;
 use directories::ProjectDirs;
 use std::path::PathBuf;
 use serde::{Deserialize, Serialize };
+use gtk::{gio, glib};
+use gtk::prelude::*;
+use gettextrs::gettext;
+use log::{info, warn};
 
 use crate::audioshare;
 
+pub const SCHEMA_ID: &str = "com.subrighteous.audiosharegtk";
+
+// Settings live in GSettings, so they can also be changed with `gsettings`
+pub fn settings() -> gio::Settings {
+    gio::Settings::new(SCHEMA_ID)
+}
+
+// Ways to get the user's attention besides a desktop notification
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStyle {
+    Sound,
+    Attention,
+    Banner,
+}
+
+impl AlertStyle {
+    pub const ALL: [AlertStyle; 3] = [AlertStyle::Sound, AlertStyle::Attention, AlertStyle::Banner];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            AlertStyle::Sound => "sound",
+            AlertStyle::Attention => "attention",
+            AlertStyle::Banner => "banner",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        AlertStyle::ALL.into_iter().find(|style| style.key() == key)
+    }
+}
+
+pub fn alerts_from_settings(settings: &gio::Settings, key: &str) -> Vec<AlertStyle> {
+    settings
+        .strv(key)
+        .iter()
+        .filter_map(|style| AlertStyle::from_key(style.as_str()))
+        .collect()
+}
+
+pub fn set_alerts(settings: &gio::Settings, key: &str, styles: &[AlertStyle]) -> Result<(), glib::BoolError> {
+    let keys: Vec<&str> = styles.iter().map(AlertStyle::key).collect();
+    settings.set_strv(key, keys.as_slice())
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppConfig {
     pub audio_endpoint: String,
@@ -18,31 +69,58 @@ pub struct AppConfig {
     pub notification_error: bool,
     pub notification_device_connect: bool,
     pub notification_device_disconnect: bool,
+    #[serde(default = "default_firewall_test_timeout")]
+    pub firewall_test_timeout: u64,
+    #[serde(default = "default_advertise_service")]
+    pub advertise_service: bool,
+    // Nicknames by MAC address, or by IP when the MAC is unknown.
+    // An empty name marks a device the user chose not to name.
+    #[serde(default)]
+    pub device_names: HashMap<String, String>,
+    // Client IPs that were blocked through the firewall
+    #[serde(default)]
+    pub blocked_devices: Vec<String>,
+    // Extra alerts, on top of the notification switches above
+    #[serde(default)]
+    pub error_alerts: Vec<AlertStyle>,
+    #[serde(default)]
+    pub device_connect_alerts: Vec<AlertStyle>,
+    #[serde(default)]
+    pub device_disconnect_alerts: Vec<AlertStyle>,
+    // What to do when the server stops by itself or its settings change
+    #[serde(default)]
+    pub restart_on_failure: bool,
+    #[serde(default = "default_restart_attempts")]
+    pub restart_attempts: u32,
+    #[serde(default = "default_restart_on_settings_change")]
+    pub restart_on_settings_change: bool,
 }
 
-impl AppConfig {
+fn default_firewall_test_timeout() -> u64 {
+    10
+}
 
-    fn default() -> Self {
-        let audio_endpoint_name: String;
-        let audio_encoding_name: String;
+fn default_advertise_service() -> bool {
+    true
+}
 
-        let server_ip: String = audioshare::get_local_ipv4();
+fn default_restart_attempts() -> u32 {
+    3
+}
 
-        if let Some((_, _id, name)) = audioshare::get_default_endpoint() {
-            audio_endpoint_name = name;
-        }else{
-            audio_endpoint_name = String::new();
-        }
+fn default_restart_on_settings_change() -> bool {
+    true
+}
 
-        if let Some((_,desc)) = audioshare::get_default_encoding(){
-            audio_encoding_name = desc;
-        }else{
-            audio_encoding_name = String::new();
-        }
+impl AppConfig {
 
+    fn default() -> Self {
+        let server_ip: String = audioshare::get_local_ipv4();
+
+        // Empty picks the default endpoint and encoding once as-cmd has listed them
         Self {
-            audio_endpoint: audio_endpoint_name.to_string(),
-            audio_encoding: audio_encoding_name.to_string(),
+            audio_endpoint: String::new(),
+            audio_encoding: String::new(),
             server_ip: server_ip.to_string(),
             server_port: 65530,
             minimize_on_exit: false,
@@ -52,6 +130,16 @@ impl AppConfig {
             notification_error: true,
             notification_device_connect: true,
             notification_device_disconnect: false,
+            firewall_test_timeout: default_firewall_test_timeout(),
+            advertise_service: default_advertise_service(),
+            device_names: HashMap::new(),
+            blocked_devices: Vec::new(),
+            error_alerts: Vec::new(),
+            device_connect_alerts: Vec::new(),
+            device_disconnect_alerts: Vec::new(),
+            restart_on_failure: false,
+            restart_attempts: default_restart_attempts(),
+            restart_on_settings_change: default_restart_on_settings_change(),
         }
     }
 
@@ -62,7 +150,7 @@ impl AppConfig {
                 match serde_json::from_str::<AppConfig>(&contents) {
                     Ok(cfg) => {
                         if let Err(e) = cfg.validate(){
-                            eprint!("Config validation failed : {}. Using defaults", e);
+                            warn!("Config validation failed : {}. Using defaults", e);
                             AppConfig::default()
                         }else{
                             cfg
@@ -70,13 +158,13 @@ impl AppConfig {
 
                     }
                     Err(e) => {
-                        eprintln!("Config parse error: {}. Using defaults.", e);
+                        warn!("Config parse error: {}. Using defaults.", e);
                         AppConfig::default()
                     }
                 }
             }
             Err(_)=>{
-                eprintln!("No config file found. Using defaults");
+                info!("No config file found. Using defaults");
                 AppConfig::default()
             }
 
@@ -84,58 +172,132 @@ impl AppConfig {
 
     }
 
-    pub fn validate(&self) -> Result<(), String> {
-        if self.server_ip.is_empty() {
-            return Err("server_ip cannot be empty".into());
-        }
-        if self.server_port == 0 {
-            return Err("server_port must be between 1 and 65535".into());
+    pub fn from_settings(settings: &gio::Settings) -> Self {
+        Self {
+            audio_endpoint: settings.string("audio-endpoint").to_string(),
+            audio_encoding: settings.string("audio-encoding").to_string(),
+            server_ip: settings.string("server-ip").to_string(),
+            server_port: settings.get::<u16>("server-port"),
+            minimize_on_exit: settings.boolean("minimize-on-exit"),
+            auto_start_server: settings.boolean("auto-start-server"),
+            keep_last_state: settings.boolean("keep-last-state"),
+            last_server_state: settings.boolean("last-server-state"),
+            notification_error: settings.boolean("notification-error"),
+            notification_device_connect: settings.boolean("notification-device-connect"),
+            notification_device_disconnect: settings.boolean("notification-device-disconnect"),
+            firewall_test_timeout: settings.uint("firewall-test-timeout") as u64,
+            advertise_service: settings.boolean("advertise-service"),
+            device_names: settings.get::<HashMap<String, String>>("device-names"),
+            blocked_devices: settings.strv("blocked-devices").iter().map(|ip| ip.to_string()).collect(),
+            error_alerts: alerts_from_settings(settings, "error-alerts"),
+            device_connect_alerts: alerts_from_settings(settings, "device-connect-alerts"),
+            device_disconnect_alerts: alerts_from_settings(settings, "device-disconnect-alerts"),
+            restart_on_failure: settings.boolean("restart-on-failure"),
+            restart_attempts: settings.uint("restart-attempts"),
+            restart_on_settings_change: settings.boolean("restart-on-settings-change"),
         }
-        if self.audio_endpoint.is_empty() {
-            return Err("audio_endpoint cannot be empty".into());
+    }
+
+    pub fn write_settings(&self, settings: &gio::Settings) -> Result<(), glib::BoolError> {
+        let blocked_devices: Vec<&str> = self.blocked_devices.iter().map(String::as_str).collect();
+
+        settings.set_string("audio-endpoint", &self.audio_endpoint)?;
+        settings.set_string("audio-encoding", &self.audio_encoding)?;
+        settings.set_string("server-ip", &self.server_ip)?;
+        settings.set("server-port", self.server_port)?;
+        settings.set_boolean("minimize-on-exit", self.minimize_on_exit)?;
+        settings.set_boolean("auto-start-server", self.auto_start_server)?;
+        settings.set_boolean("keep-last-state", self.keep_last_state)?;
+        settings.set_boolean("last-server-state", self.last_server_state)?;
+        settings.set_boolean("notification-error", self.notification_error)?;
+        settings.set_boolean("notification-device-connect", self.notification_device_connect)?;
+        settings.set_boolean("notification-device-disconnect", self.notification_device_disconnect)?;
+        settings.set_uint("firewall-test-timeout", self.firewall_test_timeout.clamp(1, 300) as u32)?;
+        settings.set_boolean("advertise-service", self.advertise_service)?;
+        settings.set("device-names", &self.device_names)?;
+        settings.set_strv("blocked-devices", blocked_devices.as_slice())?;
+        set_alerts(settings, "error-alerts", &self.error_alerts)?;
+        set_alerts(settings, "device-connect-alerts", &self.device_connect_alerts)?;
+        set_alerts(settings, "device-disconnect-alerts", &self.device_disconnect_alerts)?;
+        settings.set_boolean("restart-on-failure", self.restart_on_failure)?;
+        settings.set_uint("restart-attempts", self.restart_attempts.clamp(1, 10))?;
+        settings.set_boolean("restart-on-settings-change", self.restart_on_settings_change)?;
+        Ok(())
+    }
+
+    // An empty address means "pick for me", which can only be answered at
+    // runtime. Empty endpoints and encodings are left for AudioDevices.
+    fn fill_runtime_defaults(&mut self) {
+        if self.server_ip.is_empty() {
+            self.server_ip = audioshare::get_local_ipv4();
         }
-        if self.audio_encoding.is_empty() {
-            return Err("audio_encoding cannot be empty".into());
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        validate_server_ip(&self.server_ip)?;
+        validate_server_port(&self.server_port.to_string())?;
+        if self.firewall_test_timeout == 0 {
+            return Err("firewall_test_timeout must be at least 1 second".into());
         }
         Ok(())
     }
 }
 
+// Shared by the config and the main window entries
+pub fn validate_server_ip(ip: &str) -> Result<(), String> {
+    if ip.is_empty() {
+        return Err(gettext("The IP address cannot be empty"));
+    }
+    if ip.parse::<IpAddr>().is_err() {
+        return Err(gettext("Not a valid IPv4 or IPv6 address"));
+    }
+    Ok(())
+}
+
+pub fn validate_server_port(port: &str) -> Result<u16, String> {
+    match port.trim().parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(gettext("The port must be a number between 1 and 65535")),
+    }
+}
+
 pub fn get_config_path() -> Option<PathBuf> {
     ProjectDirs::from("com", "subrighteous", "AudioShareGTK")
         .map(|dirs| dirs.config_dir().join("config.json"))
 }
 
 pub fn load_or_create_config() -> io::Result<AppConfig> {
-    let path = get_config_path().expect("No valid config path available");
+    let settings = settings();
 
-    if path.exists() {
-        let config: AppConfig = AppConfig::load(path);
-
-        Ok(config)
-    } else {
-
-        let config = create_config(path);
-
-        config
+    if !settings.boolean("config-imported") {
+        import_config(&settings)?;
     }
-}
 
-pub fn create_config(path: PathBuf) -> io::Result<AppConfig>{
-    let config = AppConfig::default();
-
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+    let mut config = AppConfig::from_settings(&settings);
+    if config.validate().is_err() {
+        config.fill_runtime_defaults();
+        save_config(&config)?;
     }
 
-    let data = serde_json::to_string_pretty(&config)?;
-    fs::write(&path, data)?;
     Ok(config)
 }
 
-pub fn save_config(config: &AppConfig) -> io::Result<()> {
-    let path = get_config_path().expect("No valid config path available");
-    let data = serde_json::to_string_pretty(config)?;
-    fs::write(path, data)?;
+// Copy the settings from config.json into GSettings, once. The file is left
+// in place so going back to an older version doesn't lose anything.
+fn import_config(settings: &gio::Settings) -> io::Result<()> {
+    let config = match get_config_path() {
+        Some(path) if path.exists() => {
+            info!("Importing settings from {:?}", path);
+            AppConfig::load(path)
+        }
+        _ => AppConfig::default(),
+    };
+
+    save_config(&config)?;
+    settings.set_boolean("config-imported", true).map_err(io::Error::other)?;
     Ok(())
 }
+
+pub fn save_config(config: &AppConfig) -> io::Result<()> {
+    config.write_settings(&settings()).map_err(io::Error::other)
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::fs;
+
+// The kernel's IPv4 neighbour table
+const ARP_TABLE: &str = "/proc/net/arp";
+
+// as-cmd may report IPv4 clients as IPv4-mapped IPv6 addresses
+pub fn plain_ip(ip: &str) -> &str {
+    ip.strip_prefix("::ffff:").unwrap_or(ip)
+}
+
+// Look up the MAC address of a client on the local network. Only IPv4
+// neighbours are listed in /proc, so IPv6 clients are never found.
+pub fn lookup_mac(ip: &str) -> Option<String> {
+    find_mac(&fs::read_to_string(ARP_TABLE).ok()?, ip)
+}
+
+// The MAC for ip in the text of /proc/net/arp
+fn find_mac(table: &str, ip: &str) -> Option<String> {
+    let ip = plain_ip(ip);
+
+    // IP address, HW type, Flags, HW address, Mask, Device
+    table.lines().skip(1).find_map(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        match columns.as_slice() {
+            [entry_ip, _, flags, mac, ..]
+                if *entry_ip == ip && *flags != "0x0" && *mac != "00:00:00:00:00:00" =>
+            {
+                Some(mac.to_lowercase())
+            }
+            _ => None,
+        }
+    })
+}
+
+// The key a device is remembered by: its MAC when known, since that survives
+// DHCP handing out a new address, otherwise its IP
+pub fn device_key(ip: &str) -> String {
+    lookup_mac(ip).unwrap_or_else(|| plain_ip(ip).to_string())
+}
+
+// The nickname for a device, if the user gave it one. An empty name means the
+// device was seen but the user chose not to name it.
+pub fn nickname<'a>(names: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    names.get(key).map(String::as_str).filter(|name| !name.is_empty())
+}
+
+// What to call a client in the UI, e.g. "Living Room TV (192.168.1.47)"
+pub fn display_name(names: &HashMap<String, String>, key: &str, ip: &str) -> String {
+    match nickname(names, key) {
+        Some(name) => format!("{} ({})", name, plain_ip(ip)),
+        None => plain_ip(ip).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.5      0x1         0x2         AA:BB:CC:00:11:22     *        wlan0
+192.168.1.50     0x1         0x2         aa:bb:cc:00:11:33     *        wlan0
+192.168.1.7      0x1         0x0         00:00:00:00:00:00     *        wlan0
+";
+
+    #[test]
+    fn strips_the_ipv4_mapped_prefix() {
+        assert_eq!(plain_ip("::ffff:192.168.1.5"), "192.168.1.5");
+        assert_eq!(plain_ip("192.168.1.5"), "192.168.1.5");
+        assert_eq!(plain_ip("fd00::5"), "fd00::5");
+    }
+
+    #[test]
+    fn finds_macs_in_the_arp_table() {
+        assert_eq!(find_mac(TABLE, "192.168.1.5").as_deref(), Some("aa:bb:cc:00:11:22"));
+        assert_eq!(find_mac(TABLE, "::ffff:192.168.1.50").as_deref(), Some("aa:bb:cc:00:11:33"));
+        // Incomplete entries don't count
+        assert_eq!(find_mac(TABLE, "192.168.1.7"), None);
+        assert_eq!(find_mac(TABLE, "fd00::5"), None);
+        assert_eq!(find_mac(TABLE, ""), None);
+    }
+}
@@ -0,0 +1,227 @@
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use get_if_addrs::get_if_addrs;
+
+use crate::audioshare::{self, LogLine, ProcessStopReason, ServerState};
+use crate::config::VERSION;
+use crate::configfile::AppConfig;
+use crate::logging;
+
+// How much of the app log file goes into a report
+const APP_LOG_LINES: usize = 300;
+
+// Everything the report needs from the running app. The rest is collected
+// while generating, which runs as-cmd and so belongs off the main thread.
+#[derive(Debug, Clone)]
+pub struct DiagnosticsSnapshot {
+    pub config: Option<AppConfig>,
+    pub server_state: ServerState,
+    pub last_stop_reason: Option<ProcessStopReason>,
+    pub as_cmd_log: Vec<LogLine>,
+}
+
+// Hide the host part of an address but keep enough to tell networks apart
+pub fn mask_ip(ip: &str) -> String {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.x", a, b, c)
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}:{:x}::x", segments[0], segments[1], segments[2], segments[3])
+        }
+        Err(_) => "<invalid>".to_string(),
+    }
+}
+
+// Run mask_ip over every address in free text, like log lines that mention
+// clients ("accept 192.168.1.20:50412") or the bind address
+pub fn mask_addresses(text: &str) -> String {
+    let is_address_char = |c: char| c.is_ascii_hexdigit() || c == '.' || c == ':';
+
+    let mut masked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(is_address_char) {
+        masked.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_address_char(c)).unwrap_or(rest.len());
+        let (candidate, after) = rest.split_at(end);
+        masked.push_str(&mask_candidate(candidate));
+        rest = after;
+    }
+    masked.push_str(rest);
+    masked
+}
+
+// One run of address-like characters, which may also be a word, a time or
+// an address with a port or a full stop after it
+fn mask_candidate(candidate: &str) -> String {
+    // Hex words and "::" in paths are not addresses
+    if !candidate.contains(|c: char| c.is_ascii_digit()) {
+        return candidate.to_string();
+    }
+
+    let trimmed = candidate.trim_end_matches(['.', ':']);
+    let trailer = &candidate[trimmed.len()..];
+
+    if trimmed.parse::<IpAddr>().is_ok() {
+        return mask_ip(trimmed) + trailer;
+    }
+
+    // An IPv4 address with a port
+    if let Some((ip, port)) = trimmed.rsplit_once(':') {
+        if ip.parse::<std::net::Ipv4Addr>().is_ok() && port.chars().all(|c| c.is_ascii_digit()) {
+            return mask_ip(ip) + ":" + port + trailer;
+        }
+    }
+
+    candidate.to_string()
+}
+
+// Keep the vendor part of a MAC address, which can help with device quirks
+fn mask_device_key(key: &str) -> String {
+    if key.parse::<IpAddr>().is_ok() {
+        return mask_ip(key);
+    }
+
+    let vendor: Vec<&str> = key.split(':').take(3).collect();
+    vendor.join(":") + ":xx:xx:xx"
+}
+
+fn sanitize_config(config: &AppConfig) -> String {
+    let mut config = config.clone();
+    config.server_ip = mask_ip(&config.server_ip);
+
+    // Nicknames tend to contain people's names
+    config.device_names = config
+        .device_names
+        .iter()
+        .map(|(key, name)| {
+            let name = if name.is_empty() { "" } else { "<named>" };
+            (mask_device_key(key), name.to_string())
+        })
+        .collect();
+    config.blocked_devices = config.blocked_devices.iter().map(|ip| mask_ip(ip)).collect();
+
+    serde_json::to_string_pretty(&config).unwrap_or_else(|e| format!("<failed to serialize: {}>", e))
+}
+
+fn tail_file(path: &Path, lines: usize) -> Option<String> {
+    let contents = fs::read_to_string(path).ok()?;
+    let all: Vec<&str> = contents.lines().collect();
+    Some(all[all.len().saturating_sub(lines)..].join("\n"))
+}
+
+// Write a plain text report for attaching to bug reports
+pub fn generate_report(snapshot: &DiagnosticsSnapshot) -> String {
+    let mut report = String::new();
+
+    let _ = writeln!(report, "AudioShareGTK diagnostics");
+    let _ = writeln!(report, "=========================");
+    let _ = writeln!(report, "App version: {}", VERSION);
+    let _ = writeln!(report, "Flatpak: {}", Path::new("/.flatpak-info").exists());
+    for var in ["XDG_CURRENT_DESKTOP", "XDG_SESSION_TYPE"] {
+        let _ = writeln!(report, "{}: {}", var, env::var(var).unwrap_or_default());
+    }
+
+    let _ = writeln!(report, "\n## as-cmd");
+    match audioshare::get_version() {
+        Some(version) => {
+            let _ = writeln!(report, "Version: {}", version);
+        }
+        None => {
+            let _ = writeln!(report, "Version: <as-cmd could not be run>");
+        }
+    }
+    let _ = writeln!(report, "Server state: {:?}", snapshot.server_state);
+    match &snapshot.last_stop_reason {
+        Some(reason) => {
+            let _ = writeln!(report, "Last stop reason: {:?}", reason);
+        }
+        None => {
+            let _ = writeln!(report, "Last stop reason: <not stopped yet>");
+        }
+    }
+
+    let _ = writeln!(report, "\n## Audio endpoints");
+    for (is_default, id, name) in audioshare::get_audio_endpoints() {
+        let _ = writeln!(report, "{} {:>3} {}", if is_default { "*" } else { " " }, id, name);
+    }
+
+    let _ = writeln!(report, "\n## Audio encodings");
+    for (key, description) in audioshare::get_audio_encoding() {
+        let _ = writeln!(report, "{:<16} {}", key, description);
+    }
+
+    let _ = writeln!(report, "\n## Network interfaces");
+    match get_if_addrs() {
+        Ok(interfaces) => {
+            for iface in interfaces {
+                let _ = writeln!(report, "{:<16} {}", iface.name, mask_ip(&iface.ip().to_string()));
+            }
+        }
+        Err(e) => {
+            let _ = writeln!(report, "<failed to list interfaces: {}>", e);
+        }
+    }
+
+    let _ = writeln!(report, "\n## Configuration");
+    match &snapshot.config {
+        Some(config) => {
+            let _ = writeln!(report, "{}", sanitize_config(config));
+        }
+        None => {
+            let _ = writeln!(report, "<not loaded>");
+        }
+    }
+
+    let _ = writeln!(report, "\n## Recent as-cmd output");
+    for line in &snapshot.as_cmd_log {
+        let _ = writeln!(report, "{:?} {:<5} {}", line.stream, line.level, mask_addresses(&line.text));
+    }
+
+    let _ = writeln!(report, "\n## Recent app log");
+    match logging::get_log_file_path().and_then(|path| tail_file(&path, APP_LOG_LINES)) {
+        Some(log) => {
+            let _ = writeln!(report, "{}", mask_addresses(&log));
+        }
+        None => {
+            let _ = writeln!(report, "<no log file>");
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_addresses_in_log_text() {
+        let log = "[info] accept 192.168.1.20:50412\n\
+                   [info] close 192.168.1.20:50412\n\
+                   bind: 10.0.0.7:65530 listening\n\
+                   client [fd00::2]:40000 from fe80::fc:ff:fe00:1%eth0\n\
+                   Starting server thread with server ip : 172.16.4.9 server port : 65530\n\
+                   Blocked 2001:db8::42.";
+        let masked = mask_addresses(log);
+
+        for raw in ["192.168.1.20", "10.0.0.7", "fd00::2", "fe80::fc:ff:fe00:1", "172.16.4.9", "2001:db8::42"] {
+            assert!(!masked.contains(raw), "{} survived in {}", raw, masked);
+        }
+        assert!(masked.contains("[info] accept 192.168.1.x:50412"));
+        assert!(masked.contains("Blocked 2001:db8:0:0::x."));
+    }
+
+    #[test]
+    fn leaves_other_text_alone() {
+        let text = "ServerState::Running at 12:30:45.120, as-cmd 0.1.0 with encoding pcm_16bit";
+        assert_eq!(mask_addresses(text), text);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+use gtk::glib;
+use log::{info, warn};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+use crate::audioshare::ServerBinding;
+
+// DNS-SD service type clients browse for
+pub const SERVICE_TYPE: &str = "_audioshare._tcp.local.";
+
+// Publishes the running server on the local network so clients can find it
+// without typing in the address
+pub struct ServiceAdvertiser {
+    enabled: bool,
+    daemon: Option<ServiceDaemon>,
+    fullname: Option<String>,
+}
+
+impl fmt::Debug for ServiceAdvertiser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceAdvertiser")
+            .field("enabled", &self.enabled)
+            .field("fullname", &self.fullname)
+            .finish()
+    }
+}
+
+impl ServiceAdvertiser {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            daemon: None,
+            fullname: None,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.withdraw();
+        }
+    }
+
+    pub fn is_advertising(&self) -> bool {
+        self.fullname.is_some()
+    }
+
+    pub fn advertise(&mut self, binding: &ServerBinding) -> Result<(), String> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        // Only one server runs at a time
+        self.withdraw();
+
+        if self.daemon.is_none() {
+            self.daemon = Some(ServiceDaemon::new().map_err(|e| e.to_string())?);
+        }
+        let daemon = self.daemon.as_ref().unwrap();
+
+        let host = glib::host_name().to_string();
+        let instance_name = format!("AudioShare on {}", host);
+        let host_name = format!("{}.local.", host);
+
+        let properties = HashMap::from([
+            ("address".to_string(), binding.server_ip.clone()),
+            ("port".to_string(), binding.server_port.to_string()),
+            ("encoding".to_string(), binding.encoding_key.clone()),
+            ("hostname".to_string(), host.clone()),
+        ]);
+
+        // A wildcard bind is reachable on every interface, let the daemon
+        // fill in the addresses itself
+        let wildcard = binding
+            .server_ip
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_unspecified())
+            .unwrap_or(false);
+        let address = if wildcard { "" } else { binding.server_ip.as_str() };
+
+        let mut service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance_name,
+            &host_name,
+            address,
+            binding.server_port,
+            properties,
+        )
+        .map_err(|e| e.to_string())?;
+
+        if wildcard {
+            service = service.enable_addr_auto();
+        }
+
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service).map_err(|e| e.to_string())?;
+
+        info!("Advertising {} on {}:{}", fullname, binding.server_ip, binding.server_port);
+        self.fullname = Some(fullname);
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self) {
+        if let (Some(daemon), Some(fullname)) = (self.daemon.as_ref(), self.fullname.take()) {
+            match daemon.unregister(&fullname) {
+                Ok(_) => info!("Stopped advertising {}", fullname),
+                Err(e) => warn!("Failed to withdraw {}: {}", fullname, e),
+            }
+        }
+    }
+}
+
+impl Drop for ServiceAdvertiser {
+    fn drop(&mut self) {
+        self.withdraw();
+        if let Some(daemon) = self.daemon.take() {
+            let _ = daemon.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use mdns_sd::ServiceEvent;
+    use std::time::{Duration, Instant};
+
+    // Wait for the first browse event that matches, up to a few seconds
+    fn wait_for<T>(receiver: &mdns_sd::Receiver<ServiceEvent>, mut matches: impl FnMut(ServiceEvent) -> Option<T>) -> Option<T> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match receiver.recv_timeout(left) {
+                Ok(event) => {
+                    if let Some(found) = matches(event) {
+                        return Some(found);
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        None
+    }
+
+    // Needs multicast on a real interface, which sandboxes and CI runners
+    // often don't have. Run it with `cargo test -- --ignored` on a desktop.
+    #[test]
+    #[ignore = "needs multicast on a real network interface"]
+    fn advertises_and_withdraws() {
+        let binding = test_support::binding(&crate::audioshare::get_local_ipv4());
+
+        let browser = ServiceDaemon::new().unwrap();
+        let receiver = browser.browse(SERVICE_TYPE).unwrap();
+
+        let mut advertiser = ServiceAdvertiser::new();
+        advertiser.advertise(&binding).unwrap();
+        assert!(advertiser.is_advertising());
+        let fullname = advertiser.fullname.clone().unwrap();
+
+        let info = wait_for(&receiver, |event| match event {
+            ServiceEvent::ServiceResolved(info) if info.get_fullname() == fullname => Some(info),
+            _ => None,
+        })
+        .expect("the service was never resolved");
+
+        assert_eq!(info.get_port(), test_support::PORT);
+        assert_eq!(info.get_property_val_str("address"), Some(binding.server_ip.as_str()));
+        assert_eq!(info.get_property_val_str("port"), Some(test_support::PORT.to_string().as_str()));
+        assert_eq!(info.get_property_val_str("encoding"), Some(test_support::ENCODING));
+        assert_eq!(info.get_property_val_str("hostname"), Some(glib::host_name().as_str()));
+
+        advertiser.withdraw();
+        assert!(!advertiser.is_advertising());
+
+        let removed = wait_for(&receiver, |event| match event {
+            ServiceEvent::ServiceRemoved(_, name) if name == fullname => Some(()),
+            _ => None,
+        });
+        assert!(removed.is_some(), "the service was not withdrawn");
+
+        let _ = browser.shutdown();
+    }
+}
@@ -0,0 +1,575 @@
+#[cfg(debug_assertions)]
+use std::env;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+#[cfg(any(test, debug_assertions))]
+use std::sync::Mutex;
+
+use gettextrs::gettext;
+#[cfg(any(test, debug_assertions))]
+use log::info;
+
+// Comment attached to the nftables rules added to chains we don't own
+const NFT_RULE_COMMENT: &str = "audiosharegtk";
+
+// Our own nftables chain in the inet filter table. The input chain jumps to
+// it once, so the allow rules can be replaced or dropped with a flush
+// instead of looking up rule handles.
+const NFT_CHAIN: &str = "audiosharegtk";
+
+// The result of running a command
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Something that can run commands on the host. The firewall helpers only talk
+// to the system through this so they can be exercised without a real firewall.
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<CommandOutput>;
+}
+
+// Runs commands on the host, escaping the Flatpak sandbox when needed
+#[derive(Debug, Default)]
+pub struct HostCommandRunner;
+
+impl CommandRunner for HostCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<CommandOutput> {
+        let output = if Path::new("/.flatpak-info").exists() {
+            Command::new("flatpak-spawn")
+                .arg("--host")
+                .arg(program)
+                .args(args)
+                .output()?
+        } else {
+            Command::new(program).args(args).output()?
+        };
+
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        })
+    }
+}
+
+// Pretends that the given firewall is active and records every command it is
+// asked to run instead of touching the system.
+//
+// Debug builds enable it with AUDIOSHARE_FAKE_FIREWALL=firewalld|ufw|nftables|none
+#[cfg(any(test, debug_assertions))]
+#[derive(Debug)]
+pub struct FakeCommandRunner {
+    active: Option<FirewallBackend>,
+    // Make every rule change fail, as if the polkit prompt was dismissed
+    fail_changes: bool,
+    // Act like nftables without an inet filter table
+    missing_nft_chain: bool,
+    pub commands: Mutex<Vec<String>>,
+}
+
+#[cfg(any(test, debug_assertions))]
+impl FakeCommandRunner {
+    pub fn new(active: Option<FirewallBackend>) -> Self {
+        Self {
+            active,
+            fail_changes: false,
+            missing_nft_chain: false,
+            commands: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[cfg(test)]
+    fn failing(mut self) -> Self {
+        self.fail_changes = true;
+        self
+    }
+
+    #[cfg(test)]
+    fn without_nft_chain(mut self) -> Self {
+        self.missing_nft_chain = true;
+        self
+    }
+}
+
+#[cfg(any(test, debug_assertions))]
+impl CommandRunner for FakeCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<CommandOutput> {
+        let command = std::iter::once(program)
+            .chain(args.iter().copied())
+            .collect::<Vec<&str>>()
+            .join(" ");
+        info!("Fake firewall command: {}", command);
+        self.commands.lock().unwrap().push(command);
+
+        let output = |success: bool, stdout: &str| CommandOutput {
+            success,
+            stdout: stdout.to_string(),
+            stderr: if success { String::new() } else { "Not authorized".into() },
+        };
+
+        if self.missing_nft_chain && args.contains(&"nft") {
+            // find itself still exits fine when an -exec fails
+            return Ok(CommandOutput {
+                success: args.first() == Some(&"find"),
+                stdout: String::new(),
+                stderr: "Error: Could not process rule: No such file or directory".into(),
+            });
+        }
+
+        Ok(match (program, args) {
+            ("firewall-cmd", ["--state"]) if self.active == Some(FirewallBackend::Firewalld) => output(true, "running\n"),
+            ("systemctl", ["is-active", "ufw"]) if self.active == Some(FirewallBackend::Ufw) => output(true, "active\n"),
+            ("systemctl", ["is-active", "nftables"]) if self.active == Some(FirewallBackend::Nftables) => output(true, "active\n"),
+            ("firewall-cmd", ["--state"]) | ("systemctl", ["is-active", _]) => output(false, ""),
+            // A chain of changes gets to the final -print when all of them worked
+            ("pkexec", ["find", ..]) => output(!self.fail_changes, if self.fail_changes { "" } else { "/\n" }),
+            // Anything else is a single rule change
+            _ => output(!self.fail_changes, ""),
+        })
+    }
+}
+
+// Get the command runner to use. Debug builds honor AUDIOSHARE_FAKE_FIREWALL.
+pub fn default_runner() -> Box<dyn CommandRunner> {
+    #[cfg(debug_assertions)]
+    match env::var("AUDIOSHARE_FAKE_FIREWALL").ok().as_deref() {
+        Some("firewalld") => return Box::new(FakeCommandRunner::new(Some(FirewallBackend::Firewalld))),
+        Some("ufw") => return Box::new(FakeCommandRunner::new(Some(FirewallBackend::Ufw))),
+        Some("nftables") => return Box::new(FakeCommandRunner::new(Some(FirewallBackend::Nftables))),
+        Some(_) => return Box::new(FakeCommandRunner::new(None)),
+        None => {}
+    }
+
+    Box::new(HostCommandRunner)
+}
+
+// One command as the program and its arguments. Nothing goes through a
+// shell, so an argument can't turn into a second command.
+pub type FirewallCommand = Vec<String>;
+
+fn command(args: &[&str]) -> FirewallCommand {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+// One command of a rule change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirewallStep {
+    // Has to work for the change to go on
+    Run(FirewallCommand),
+    // Allowed to fail, like creating something that is already there
+    Try(FirewallCommand),
+}
+
+impl FirewallStep {
+    // How the step is shown to the user, quoted so it can be pasted into a
+    // terminal
+    pub fn display(&self) -> String {
+        let command = match self {
+            FirewallStep::Run(command) | FirewallStep::Try(command) => command,
+        };
+        command
+            .iter()
+            .map(|arg| if arg.contains([' ', '"']) { format!("'{arg}'") } else { arg.clone() })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallBackend {
+    Firewalld,
+    Ufw,
+    Nftables,
+}
+
+impl FirewallBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FirewallBackend::Firewalld => "firewalld",
+            FirewallBackend::Ufw => "ufw",
+            FirewallBackend::Nftables => "nftables",
+        }
+    }
+
+    // The steps that open the port for both the tcp control connection and
+    // the udp audio stream
+    pub fn add_rule_steps(&self, port: u16) -> Vec<FirewallStep> {
+        match self {
+            FirewallBackend::Firewalld => {
+                let ports = [format!("--add-port={port}/tcp"), format!("--add-port={port}/udp")];
+                vec![
+                    FirewallStep::Run(command(&["firewall-cmd", &ports[0], &ports[1]])),
+                    FirewallStep::Run(command(&["firewall-cmd", "--permanent", &ports[0], &ports[1]])),
+                ]
+            }
+            // Without a protocol ufw allows both
+            FirewallBackend::Ufw => vec![FirewallStep::Run(command(&["ufw", "allow", &port.to_string()]))],
+            FirewallBackend::Nftables => vec![
+                // Only the first time, the jump would be added again otherwise
+                FirewallStep::Try(command(&[
+                    "nft",
+                    &format!(
+                        "create chain inet filter {NFT_CHAIN}; \
+                         add rule inet filter input jump {NFT_CHAIN} comment \"{NFT_RULE_COMMENT}\""
+                    ),
+                ])),
+                FirewallStep::Run(command(&[
+                    "nft",
+                    &format!(
+                        "flush chain inet filter {NFT_CHAIN}; \
+                         add rule inet filter {NFT_CHAIN} tcp dport {port} accept; \
+                         add rule inet filter {NFT_CHAIN} udp dport {port} accept"
+                    ),
+                ])),
+            ],
+        }
+    }
+
+    // The steps that stop one client address from reaching the port
+    pub fn block_source_steps(&self, ip: IpAddr, port: u16) -> Vec<FirewallStep> {
+        match self {
+            FirewallBackend::Firewalld => {
+                let family = if ip.is_ipv6() { "ipv6" } else { "ipv4" };
+                let rules: Vec<String> = ["tcp", "udp"]
+                    .iter()
+                    .map(|protocol| {
+                        format!("--add-rich-rule=rule family=\"{family}\" source address=\"{ip}\" port port=\"{port}\" protocol=\"{protocol}\" reject")
+                    })
+                    .collect();
+                vec![
+                    FirewallStep::Run(command(&["firewall-cmd", &rules[0], &rules[1]])),
+                    FirewallStep::Run(command(&["firewall-cmd", "--permanent", &rules[0], &rules[1]])),
+                ]
+            }
+            // Inserted first so it wins over the allow rule
+            FirewallBackend::Ufw => vec![FirewallStep::Run(command(&[
+                "ufw", "insert", "1", "deny", "from", &ip.to_string(), "to", "any", "port", &port.to_string(),
+            ]))],
+            // Ahead of the jump to our chain so it wins over the allow rules
+            FirewallBackend::Nftables => {
+                let family = if ip.is_ipv6() { "ip6" } else { "ip" };
+                let rules: Vec<String> = ["tcp", "udp"]
+                    .iter()
+                    .map(|protocol| {
+                        format!("insert rule inet filter input {family} saddr {ip} {protocol} dport {port} drop comment \"{NFT_RULE_COMMENT}\"")
+                    })
+                    .collect();
+                vec![FirewallStep::Run(command(&["nft", &rules.join("; ")]))]
+            }
+        }
+    }
+
+    // The steps that undo add_rule_steps
+    pub fn remove_rule_steps(&self, port: u16) -> Vec<FirewallStep> {
+        match self {
+            FirewallBackend::Firewalld => {
+                let ports = [format!("--remove-port={port}/tcp"), format!("--remove-port={port}/udp")];
+                vec![
+                    FirewallStep::Run(command(&["firewall-cmd", &ports[0], &ports[1]])),
+                    FirewallStep::Run(command(&["firewall-cmd", "--permanent", &ports[0], &ports[1]])),
+                ]
+            }
+            FirewallBackend::Ufw => vec![FirewallStep::Run(command(&["ufw", "delete", "allow", &port.to_string()]))],
+            // The jump stays behind, into a chain that is now empty
+            FirewallBackend::Nftables => vec![FirewallStep::Run(command(&[
+                "nft",
+                &format!("add chain inet filter {NFT_CHAIN}; flush chain inet filter {NFT_CHAIN}"),
+            ]))],
+        }
+    }
+}
+
+// Find out which firewall is managing the host, if any.
+// firewalld is checked first since it drives nftables itself.
+pub fn detect_firewall(runner: &dyn CommandRunner) -> Option<FirewallBackend> {
+    let is_active = |program: &str, args: &[&str], expected: &str| {
+        runner
+            .run(program, args)
+            .map(|output| output.success && output.stdout.trim() == expected)
+            .unwrap_or(false)
+    };
+
+    if is_active("firewall-cmd", &["--state"], "running") {
+        Some(FirewallBackend::Firewalld)
+    } else if is_active("systemctl", &["is-active", "ufw"], "active") {
+        Some(FirewallBackend::Ufw)
+    } else if is_active("systemctl", &["is-active", "nftables"], "active") {
+        Some(FirewallBackend::Nftables)
+    } else {
+        None
+    }
+}
+
+pub fn add_rule(runner: &dyn CommandRunner, backend: FirewallBackend, port: u16) -> Result<(), String> {
+    run_privileged(runner, &backend.add_rule_steps(port)).map_err(|e| explain_error(backend, e))
+}
+
+// ip comes from the outside (the block_device action is exported on D-Bus),
+// so anything that isn't an address is refused before a command is built
+pub fn block_source(runner: &dyn CommandRunner, backend: FirewallBackend, ip: &str, port: u16) -> Result<(), String> {
+    let ip: IpAddr = ip.trim().parse().map_err(|_| gettext("Not a valid IPv4 or IPv6 address"))?;
+    // An IPv4 client seen as ::ffff:a.b.c.d still sends IPv4 packets
+    let ip = ip.to_canonical();
+    run_privileged(runner, &backend.block_source_steps(ip, port)).map_err(|e| explain_error(backend, e))
+}
+
+pub fn remove_rule(runner: &dyn CommandRunner, backend: FirewallBackend, port: u16) -> Result<(), String> {
+    run_privileged(runner, &backend.remove_rule_steps(port)).map_err(|e| explain_error(backend, e))
+}
+
+// The nftables rules go into the inet filter table's input chain, which
+// many setups don't have. nft only says "No such file or directory" then.
+fn explain_error(backend: FirewallBackend, error: String) -> String {
+    if backend == FirewallBackend::Nftables && error.contains("No such file or directory") {
+        gettext("nftables has no inet filter input chain to add the rule to.") + " "
+            + &gettext("Add the rule to your own ruleset instead.")
+    } else {
+        error
+    }
+}
+
+// pkexec runs one program and asks for the password every time, so a change
+// that takes more than one command is chained through find. It runs each
+// -exec directly without a shell, stops at the first failing Run step and
+// only gets to the final -print when everything went through.
+fn privileged_command(steps: &[FirewallStep]) -> FirewallCommand {
+    if let [FirewallStep::Run(command)] = steps {
+        return command.clone();
+    }
+
+    let mut chained = command(&["find", "/", "-maxdepth", "0"]);
+    for step in steps {
+        match step {
+            FirewallStep::Run(command) => {
+                chained.push("-exec".into());
+                chained.extend(command.iter().cloned());
+                chained.push(";".into());
+            }
+            FirewallStep::Try(command) => {
+                chained.extend(["(".into(), "-exec".into()]);
+                chained.extend(command.iter().cloned());
+                chained.extend([";".into(), "-o".into(), "-true".into(), ")".into()]);
+            }
+        }
+    }
+    chained.push("-print".into());
+    chained
+}
+
+// Run the steps as root behind a single authorization prompt
+fn run_privileged(runner: &dyn CommandRunner, steps: &[FirewallStep]) -> Result<(), String> {
+    let command = privileged_command(steps);
+    let args: Vec<&str> = command.iter().map(String::as_str).collect();
+    let chained = command.first().map(String::as_str) == Some("find");
+
+    match runner.run("pkexec", &args) {
+        Ok(output) if output.success && (!chained || output.stdout.lines().last() == Some("/")) => Ok(()),
+        Ok(output) if output.stderr.trim().is_empty() => Err(gettext("The firewall command failed")),
+        Ok(output) => Err(output.stderr.trim().to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKENDS: [FirewallBackend; 3] = [FirewallBackend::Firewalld, FirewallBackend::Ufw, FirewallBackend::Nftables];
+
+    fn commands(runner: &FakeCommandRunner) -> Vec<String> {
+        runner.commands.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn detects_each_backend() {
+        for backend in BACKENDS {
+            let runner = FakeCommandRunner::new(Some(backend));
+            assert_eq!(detect_firewall(&runner), Some(backend));
+        }
+    }
+
+    #[test]
+    fn detects_no_firewall() {
+        let runner = FakeCommandRunner::new(None);
+        assert_eq!(detect_firewall(&runner), None);
+        assert_eq!(
+            commands(&runner),
+            ["firewall-cmd --state", "systemctl is-active ufw", "systemctl is-active nftables"]
+        );
+    }
+
+    #[test]
+    fn asks_once_per_action() {
+        for backend in BACKENDS {
+            for action in ["add", "remove", "block"] {
+                let runner = FakeCommandRunner::new(Some(backend));
+                match action {
+                    "add" => add_rule(&runner, backend, 65530),
+                    "remove" => remove_rule(&runner, backend, 65530),
+                    _ => block_source(&runner, backend, "10.0.0.5", 65530),
+                }
+                .unwrap();
+                let commands = commands(&runner);
+                assert_eq!(commands.len(), 1, "{} asked {:?}", action, commands);
+                assert!(commands[0].starts_with("pkexec "));
+            }
+        }
+    }
+
+    #[test]
+    fn firewalld_rules() {
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Firewalld));
+        add_rule(&runner, FirewallBackend::Firewalld, 65530).unwrap();
+        remove_rule(&runner, FirewallBackend::Firewalld, 65530).unwrap();
+        assert_eq!(
+            commands(&runner),
+            [
+                "pkexec find / -maxdepth 0 \
+                 -exec firewall-cmd --add-port=65530/tcp --add-port=65530/udp ; \
+                 -exec firewall-cmd --permanent --add-port=65530/tcp --add-port=65530/udp ; -print",
+                "pkexec find / -maxdepth 0 \
+                 -exec firewall-cmd --remove-port=65530/tcp --remove-port=65530/udp ; \
+                 -exec firewall-cmd --permanent --remove-port=65530/tcp --remove-port=65530/udp ; -print",
+            ]
+        );
+    }
+
+    #[test]
+    fn ufw_rules() {
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Ufw));
+        add_rule(&runner, FirewallBackend::Ufw, 65530).unwrap();
+        remove_rule(&runner, FirewallBackend::Ufw, 65530).unwrap();
+        block_source(&runner, FirewallBackend::Ufw, "192.168.1.20", 65530).unwrap();
+        assert_eq!(
+            commands(&runner),
+            [
+                "pkexec ufw allow 65530",
+                "pkexec ufw delete allow 65530",
+                "pkexec ufw insert 1 deny from 192.168.1.20 to any port 65530",
+            ]
+        );
+    }
+
+    #[test]
+    fn nftables_rules() {
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Nftables));
+        add_rule(&runner, FirewallBackend::Nftables, 65530).unwrap();
+        remove_rule(&runner, FirewallBackend::Nftables, 65530).unwrap();
+        assert_eq!(
+            commands(&runner),
+            [
+                "pkexec find / -maxdepth 0 \
+                 ( -exec nft create chain inet filter audiosharegtk; \
+                 add rule inet filter input jump audiosharegtk comment \"audiosharegtk\" ; -o -true ) \
+                 -exec nft flush chain inet filter audiosharegtk; \
+                 add rule inet filter audiosharegtk tcp dport 65530 accept; \
+                 add rule inet filter audiosharegtk udp dport 65530 accept ; -print",
+                "pkexec nft add chain inet filter audiosharegtk; flush chain inet filter audiosharegtk",
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks_ipv6_with_the_right_family() {
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Nftables));
+        block_source(&runner, FirewallBackend::Nftables, "fe80::1", 65530).unwrap();
+        assert_eq!(
+            commands(&runner),
+            ["pkexec nft insert rule inet filter input ip6 saddr fe80::1 tcp dport 65530 drop comment \"audiosharegtk\"; \
+              insert rule inet filter input ip6 saddr fe80::1 udp dport 65530 drop comment \"audiosharegtk\""]
+        );
+
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Firewalld));
+        block_source(&runner, FirewallBackend::Firewalld, "fe80::1", 65530).unwrap();
+        assert_eq!(commands(&runner)[0].matches("family=\"ipv6\" source address=\"fe80::1\"").count(), 4);
+    }
+
+    #[test]
+    fn blocks_ipv4_mapped_addresses_as_ipv4() {
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Nftables));
+        block_source(&runner, FirewallBackend::Nftables, "::ffff:192.168.1.20", 65530).unwrap();
+        assert!(commands(&runner)[0].contains(" ip saddr 192.168.1.20 tcp "));
+
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Firewalld));
+        block_source(&runner, FirewallBackend::Firewalld, "::ffff:192.168.1.20", 65530).unwrap();
+        assert_eq!(commands(&runner)[0].matches("family=\"ipv4\" source address=\"192.168.1.20\"").count(), 4);
+
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Ufw));
+        block_source(&runner, FirewallBackend::Ufw, "::ffff:192.168.1.20", 65530).unwrap();
+        assert_eq!(commands(&runner), ["pkexec ufw insert 1 deny from 192.168.1.20 to any port 65530"]);
+    }
+
+    #[test]
+    fn rejects_anything_but_an_address() {
+        for ip in ["1.2.3.4; rm -rf /", "1.2.3.4' reject; '", "$(reboot)", "", "10.0.0.1 to any"] {
+            for backend in BACKENDS {
+                let runner = FakeCommandRunner::new(Some(backend));
+                assert!(block_source(&runner, backend, ip, 65530).is_err(), "{:?} was accepted", ip);
+                assert!(commands(&runner).is_empty(), "{:?} reached a command", ip);
+            }
+        }
+    }
+
+    #[test]
+    fn never_goes_through_a_shell() {
+        for backend in BACKENDS {
+            let ip = "10.0.0.5".parse().unwrap();
+            for steps in [backend.add_rule_steps(65530), backend.remove_rule_steps(65530), backend.block_source_steps(ip, 65530)] {
+                let program = privileged_command(&steps)[0].clone();
+                assert!(["find", "firewall-cmd", "ufw", "nft"].contains(&program.as_str()), "{}", program);
+            }
+        }
+    }
+
+    #[test]
+    fn reports_a_failed_change() {
+        for backend in BACKENDS {
+            let runner = FakeCommandRunner::new(Some(backend)).failing();
+            assert_eq!(add_rule(&runner, backend, 65530), Err("Not authorized".to_string()));
+
+            let runner = FakeCommandRunner::new(Some(backend)).failing();
+            assert!(remove_rule(&runner, backend, 65530).is_err());
+
+            let runner = FakeCommandRunner::new(Some(backend)).failing();
+            assert!(block_source(&runner, backend, "10.0.0.5", 65530).is_err());
+        }
+    }
+
+    #[test]
+    fn notices_a_chain_that_stopped_halfway() {
+        // find itself exits fine when an -exec fails, only the missing -print tells
+        struct StoppedHalfway;
+        impl CommandRunner for StoppedHalfway {
+            fn run(&self, _program: &str, _args: &[&str]) -> io::Result<CommandOutput> {
+                Ok(CommandOutput { success: true, stdout: String::new(), stderr: "Error: INVALID_PORT".into() })
+            }
+        }
+
+        assert_eq!(add_rule(&StoppedHalfway, FirewallBackend::Firewalld, 65530), Err("Error: INVALID_PORT".to_string()));
+    }
+
+    #[test]
+    fn explains_a_missing_nft_chain() {
+        let runner = FakeCommandRunner::new(Some(FirewallBackend::Nftables)).without_nft_chain();
+        for result in [
+            add_rule(&runner, FirewallBackend::Nftables, 65530),
+            remove_rule(&runner, FirewallBackend::Nftables, 65530),
+            block_source(&runner, FirewallBackend::Nftables, "10.0.0.5", 65530),
+        ] {
+            let error = result.unwrap_err();
+            assert!(error.contains("no inet filter input chain"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn shows_steps_ready_to_paste() {
+        let steps = FirewallBackend::Nftables.add_rule_steps(65530);
+        assert!(steps[1].display().starts_with("nft 'flush chain inet filter audiosharegtk; "));
+        assert_eq!(FirewallBackend::Ufw.add_rule_steps(65530)[0].display(), "ufw allow 65530");
+    }
+}
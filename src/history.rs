@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use directories::ProjectDirs;
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+// Older records are dropped from the file once it holds more than this
+const MAX_RECORDS: usize = 5000;
+
+pub fn get_history_path() -> Option<PathBuf> {
+    ProjectDirs::from("com", "subrighteous", "AudioShareGTK")
+        .map(|dirs| dirs.data_dir().join("history.jsonl"))
+}
+
+// One client connection, from accept to close. Times are unix seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub address: String,
+    pub started: u64,
+    pub ended: u64,
+    pub duration_secs: u64,
+    pub endpoint_id: u32,
+    pub encoding: String,
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+// Add a record to the end of the history file, one JSON object per line
+pub fn append_record(record: &SessionRecord) -> io::Result<()> {
+    let path = get_history_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let line = serde_json::to_string(record)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+// Every record in the history file, oldest first. Broken lines are skipped.
+// Reads the whole file, so keep it off the main thread.
+pub fn load_records() -> Vec<SessionRecord> {
+    match get_history_path() {
+        Some(path) => load_records_from(&path, MAX_RECORDS),
+        None => Vec::new(),
+    }
+}
+
+// Loading also trims the file down to the newest max records
+fn load_records_from(path: &Path, max: usize) -> Vec<SessionRecord> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    let mut records: Vec<SessionRecord> = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                warn!("Skipping unreadable history line: {}", e);
+                None
+            }
+        })
+        .collect();
+
+    if records.len() > max {
+        records.drain(..records.len() - max);
+        if let Err(e) = rewrite(path, &records) {
+            error!("Failed to trim the connection history: {}", e);
+        }
+    }
+
+    records
+}
+
+// Replace the file in one go, so a crash can't leave it half written
+fn rewrite(path: &Path, records: &[SessionRecord]) -> io::Result<()> {
+    let mut contents = String::new();
+    for record in records {
+        contents.push_str(&serde_json::to_string(record)?);
+        contents.push('\n');
+    }
+
+    let temp = path.with_extension("jsonl.tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(temp, path)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(records: &[SessionRecord]) -> String {
+    let mut csv = String::from("address,started,ended,duration_secs,endpoint_id,encoding\n");
+    for record in records {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            csv_field(&record.address),
+            record.started,
+            record.ended,
+            record.duration_secs,
+            record.endpoint_id,
+            csv_field(&record.encoding)
+        ));
+    }
+    csv
+}
+
+pub fn to_json(records: &[SessionRecord]) -> String {
+    serde_json::to_string_pretty(records).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[derive(Debug, Clone)]
+struct OpenSession {
+    started: SystemTime,
+    endpoint_id: u32,
+    encoding: String,
+}
+
+// Keeps track of the connected clients and writes a record when they leave
+#[derive(Debug, Clone)]
+pub struct SessionTracker {
+    open: Arc<Mutex<HashMap<String, OpenSession>>>,
+    record_notifier: broadcast::Sender<SessionRecord>,
+}
+
+impl SessionTracker {
+    pub fn new() -> Self {
+        let (record_notifier, _rx) = broadcast::channel(16);
+        Self {
+            open: Arc::new(Mutex::new(HashMap::new())),
+            record_notifier,
+        }
+    }
+
+    // Get every record as it is written
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionRecord> {
+        self.record_notifier.subscribe()
+    }
+
+    pub fn opened(&self, address: &str, endpoint_id: u32, encoding: &str) {
+        self.open.lock().unwrap().insert(
+            address.to_string(),
+            OpenSession {
+                started: SystemTime::now(),
+                endpoint_id,
+                encoding: encoding.to_string(),
+            },
+        );
+    }
+
+    pub fn closed(&self, address: &str) {
+        let session = self.open.lock().unwrap().remove(address);
+        if let Some(session) = session {
+            self.write(address, session);
+        }
+    }
+
+    // The server went away, so every client did too
+    pub fn close_all(&self) {
+        let sessions: Vec<(String, OpenSession)> = self.open.lock().unwrap().drain().collect();
+        for (address, session) in sessions {
+            self.write(&address, session);
+        }
+    }
+
+    fn write(&self, address: &str, session: OpenSession) {
+        let ended = SystemTime::now();
+        let record = SessionRecord {
+            address: address.to_string(),
+            started: unix_seconds(session.started),
+            ended: unix_seconds(ended),
+            duration_secs: ended.duration_since(session.started).map(|d| d.as_secs()).unwrap_or(0),
+            endpoint_id: session.endpoint_id,
+            encoding: session.encoding,
+        };
+
+        if let Err(e) = append_record(&record) {
+            error!("Failed to write the connection history: {}", e);
+        }
+        let _ = self.record_notifier.send(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{session_record as record, TempDir};
+
+    #[test]
+    fn trims_to_the_newest_records() {
+        let dir = TempDir::new("history");
+        let path = dir.join("history.jsonl");
+
+        let lines: Vec<String> = (0..10).map(|i| serde_json::to_string(&record(i)).unwrap()).collect();
+        fs::write(&path, lines.join("\n") + "\nnot json\n").unwrap();
+
+        let records = load_records_from(&path, 4);
+        assert_eq!(records, (6..10).map(record).collect::<Vec<_>>());
+        // The file itself was trimmed too
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+        assert_eq!(load_records_from(&path, 4), records);
+    }
+}
@@ -0,0 +1,258 @@
+use adw::prelude::*;
+use gettextrs::gettext;
+use gtk::glib;
+use log::{error, LevelFilter};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use crate::apputils;
+use crate::audioshare::{LogBuffer, LogLine, LogStream, LOG_BUFFER_LINES};
+
+// Minimum levels offered by the level dropdown, in the same order
+const LEVEL_FILTERS: [LevelFilter; 5] = [
+    LevelFilter::Trace,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+];
+
+fn format_line(line: &LogLine) -> String {
+    let time = line
+        .time
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| glib::DateTime::from_unix_local(since.as_secs() as i64).ok())
+        .and_then(|time| time.format("%H:%M:%S").ok())
+        .map(|time| time.to_string())
+        .unwrap_or_default();
+
+    let stream = match line.stream {
+        LogStream::Stdout => "out",
+        LogStream::Stderr => "err",
+    };
+
+    format!("{} {} {:<5} {}", time, stream, line.level, line.text)
+}
+
+// Whether a line passes the level filter and contains the search text
+fn line_matches(line: &LogLine, level: LevelFilter, search: &str) -> bool {
+    let search = search.to_lowercase();
+    line.level <= level && (search.is_empty() || line.text.to_lowercase().contains(&search))
+}
+
+// The buffered lines that pass the filters
+fn filter_lines(lines: &[LogLine], level: LevelFilter, search: &str) -> String {
+    lines
+        .iter()
+        .filter(|line| line_matches(line, level, search))
+        .map(format_line)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Show the as-cmd output captured since the app started
+pub fn show_log_viewer(window: &impl IsA<gtk::Window>, log_buffer: LogBuffer) {
+    let builder = gtk::Builder::from_resource("/com/subrighteous/audiosharegtk/log_viewer.ui");
+
+    let dialog: adw::Dialog = builder
+        .object("log_viewer_dialog")
+        .expect("Failed to get log_viewer_dialog");
+
+    let level_dropdown: gtk::DropDown = builder
+        .object("log_level_dropdown")
+        .expect("Failed to get log_level_dropdown");
+
+    let search_entry: gtk::SearchEntry = builder
+        .object("log_search_entry")
+        .expect("Failed to get log_search_entry");
+
+    let copy_button: gtk::Button = builder
+        .object("log_copy_button")
+        .expect("Failed to get log_copy_button");
+
+    let save_button: gtk::Button = builder
+        .object("log_save_button")
+        .expect("Failed to get log_save_button");
+
+    let text_view: gtk::TextView = builder
+        .object("log_text_view")
+        .expect("Failed to get log_text_view");
+
+    let selected_level = {
+        let level_dropdown = level_dropdown.clone();
+        Rc::new(move || {
+            LEVEL_FILTERS
+                .get(level_dropdown.selected() as usize)
+                .copied()
+                .unwrap_or(LevelFilter::Trace)
+        })
+    };
+
+    let visible_text = {
+        let selected_level = selected_level.clone();
+        let search_entry = search_entry.clone();
+        let log_buffer = log_buffer.clone();
+
+        Rc::new(move || filter_lines(&log_buffer.lines(), selected_level(), &search_entry.text()))
+    };
+
+    // Follow the newest lines
+    let scroll_to_end = {
+        let text_view = text_view.clone();
+        Rc::new(move || {
+            let mut end = text_view.buffer().end_iter();
+            text_view.scroll_to_iter(&mut end, 0.0, false, 0.0, 0.0);
+        })
+    };
+
+    let render = {
+        let text_view = text_view.clone();
+        let visible_text = visible_text.clone();
+        let scroll_to_end = scroll_to_end.clone();
+
+        Rc::new(move || {
+            text_view.buffer().set_text(&visible_text());
+            scroll_to_end();
+        })
+    };
+
+    // Add one new line at the end, dropping the oldest once there are as
+    // many as the log buffer keeps
+    let append = {
+        let text_view = text_view.clone();
+        let search_entry = search_entry.clone();
+        let scroll_to_end = scroll_to_end.clone();
+
+        move |line: &LogLine| {
+            if !line_matches(line, selected_level(), &search_entry.text()) {
+                return;
+            }
+
+            let buffer = text_view.buffer();
+            let mut end = buffer.end_iter();
+            if buffer.char_count() > 0 {
+                buffer.insert(&mut end, "\n");
+            }
+            buffer.insert(&mut end, &format_line(line));
+
+            if buffer.line_count() as usize > LOG_BUFFER_LINES {
+                let mut start = buffer.start_iter();
+                let mut second = buffer.iter_at_line(1).unwrap_or_else(|| buffer.end_iter());
+                buffer.delete(&mut start, &mut second);
+            }
+            scroll_to_end();
+        }
+    };
+
+    level_dropdown.connect_selected_notify(glib::clone!(
+        #[strong]
+        render,
+        move |_| render()
+    ));
+
+    search_entry.connect_search_changed(glib::clone!(
+        #[strong]
+        render,
+        move |_| render()
+    ));
+
+    copy_button.connect_clicked(glib::clone!(
+        #[strong]
+        visible_text,
+        move |button| {
+            button.clipboard().set_text(&visible_text());
+        }
+    ));
+
+    let parent = window.clone().upcast::<gtk::Window>();
+    save_button.connect_clicked(glib::clone!(
+        #[strong]
+        visible_text,
+        #[strong]
+        parent,
+        move |_| {
+            let contents = visible_text();
+            let parent = parent.clone();
+
+            glib::MainContext::default().spawn_local(async move {
+                let file_dialog = gtk::FileDialog::builder()
+                    .title(gettext("Save Server Log"))
+                    .initial_name("as-cmd.log")
+                    .build();
+
+                // Cancelling the dialog also ends up here
+                let Ok(file) = file_dialog.save_future(Some(&parent)).await else {
+                    return;
+                };
+
+                if let Some(path) = file.path() {
+                    if let Err(e) = std::fs::write(&path, contents) {
+                        error!("Failed to save the log to {:?}: {}", path, e);
+                        apputils::show_alert_dialog(&parent, &gettext("Could not save the log"), &e.to_string());
+                    }
+                }
+            });
+        }
+    ));
+
+    // Append new output while the dialog is open
+    let (closed_tx, mut closed_rx) = oneshot::channel::<()>();
+    let closed_tx = RefCell::new(Some(closed_tx));
+    dialog.connect_closed(move |_| {
+        if let Some(closed_tx) = closed_tx.borrow_mut().take() {
+            let _ = closed_tx.send(());
+        }
+    });
+
+    let mut log_rx = log_buffer.subscribe();
+    glib::MainContext::default().spawn_local(async move {
+        loop {
+            tokio::select! {
+                result = log_rx.recv() => match result {
+                    Ok(line) => append(&line),
+                    // Fell behind, start over from the buffer
+                    Err(RecvError::Lagged(_)) => render(),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut closed_rx => break,
+            }
+        }
+    });
+
+    // render was moved into the listener, fill in what is already buffered
+    text_view.buffer().set_text(&visible_text());
+    scroll_to_end();
+
+    dialog.present(Some(&parent));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+    use std::time::SystemTime;
+
+    fn line(level: Level, text: &str) -> LogLine {
+        LogLine { time: SystemTime::now(), stream: LogStream::Stderr, level, text: text.to_string() }
+    }
+
+    #[test]
+    fn filters_by_level_and_search() {
+        let warning = line(Level::Warn, "Client Disconnected");
+        let debug = line(Level::Debug, "buffer underrun");
+
+        assert!(line_matches(&warning, LevelFilter::Warn, ""));
+        assert!(!line_matches(&debug, LevelFilter::Warn, ""));
+        assert!(line_matches(&warning, LevelFilter::Trace, "disconnected"));
+        assert!(!line_matches(&warning, LevelFilter::Trace, "underrun"));
+
+        let shown = filter_lines(&[warning, debug], LevelFilter::Trace, "UNDERRUN");
+        assert_eq!(shown.lines().count(), 1);
+        assert!(shown.ends_with("buffer underrun"));
+    }
+}
@@ -0,0 +1,360 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use directories::ProjectDirs;
+use gtk::glib;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_IDENTIFIER: &str = "audiosharegtk";
+
+// Rotate the log file once it grows past this size, keeping a few old ones
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+const KEPT_LOG_FILES: usize = 3;
+
+// Used when RUST_LOG is not set
+const DEFAULT_FILTER: &str = "warn,audiosharegtk=info";
+
+pub fn get_log_dir() -> Option<PathBuf> {
+    ProjectDirs::from("com", "subrighteous", "AudioShareGTK")
+        .map(|dirs| dirs.data_dir().join("logs"))
+}
+
+pub fn get_log_file_path() -> Option<PathBuf> {
+    get_log_dir().map(|dir| dir.join("audiosharegtk.log"))
+}
+
+// A RUST_LOG style filter, e.g. "warn,audiosharegtk=debug"
+#[derive(Debug)]
+struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(spec: &str) -> Self {
+        let mut filter = Filter {
+            default: LevelFilter::Warn,
+            targets: Vec::new(),
+        };
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) if !target.trim().is_empty() => {
+                    if let Ok(level) = level.trim().parse() {
+                        filter.targets.push((target.trim().to_string(), level));
+                    }
+                }
+                // "=info" has no target to apply to
+                Some(_) => {}
+                None => {
+                    // A bare level sets the default, a bare target enables everything for it
+                    match directive.parse() {
+                        Ok(level) => filter.default = level,
+                        Err(_) => filter.targets.push((directive.to_string(), LevelFilter::Trace)),
+                    }
+                }
+            }
+        }
+
+        // The most specific target wins
+        filter.targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        filter
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || (target.starts_with(prefix.as_str()) && target[prefix.len()..].starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+// Appends to a log file and moves it aside once it gets too big
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size + line.len() as u64 > MAX_LOG_FILE_SIZE {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    // audiosharegtk.log -> audiosharegtk.log.1 -> ... -> audiosharegtk.log.N
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..KEPT_LOG_FILES).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+#[derive(Debug)]
+struct Logger {
+    filter: Filter,
+    journald: Option<UnixDatagram>,
+    file: Option<Mutex<RotatingFile>>,
+    // Desktop launches already send stderr to the journal, so only echo
+    // there when someone is watching a terminal or journald is missing
+    use_stderr: bool,
+}
+
+impl Logger {
+    fn write_journald(&self, socket: &UnixDatagram, record: &Record) {
+        let priority = match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+
+        let mut message = Vec::new();
+        append_journald_field(&mut message, "PRIORITY", &priority.to_string());
+        append_journald_field(&mut message, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+        append_journald_field(&mut message, "TARGET", record.target());
+        if let Some(file) = record.file() {
+            append_journald_field(&mut message, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            append_journald_field(&mut message, "CODE_LINE", &line.to_string());
+        }
+        append_journald_field(&mut message, "MESSAGE", &record.args().to_string());
+
+        let _ = socket.send(&message);
+    }
+}
+
+// Serialize a field in the journald native protocol. Values with newlines
+// need the length prefixed binary form.
+fn append_journald_field(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Some(socket) = &self.journald {
+            self.write_journald(socket, record);
+        }
+
+        if !self.use_stderr && self.file.is_none() {
+            return;
+        }
+
+        let timestamp = glib::DateTime::now_local()
+            .and_then(|now| {
+                now.format("%Y-%m-%d %H:%M:%S")
+                    .map(|time| format!("{}.{:03}", time, now.microsecond() / 1000))
+            })
+            .unwrap_or_default();
+        let line = format!("{} {:<5} {}: {}\n", timestamp, record.level(), record.target(), record.args());
+
+        if self.use_stderr {
+            let _ = io::stderr().write_all(line.as_bytes());
+        }
+
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+// Install the logger. Call once, before anything logs.
+pub fn init() {
+    let spec = env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    let filter = Filter::parse(&spec);
+
+    let journald = if Path::new(JOURNALD_SOCKET).exists() {
+        UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(JOURNALD_SOCKET).map(|_| socket))
+            .ok()
+    } else {
+        None
+    };
+
+    let file = get_log_file_path().and_then(|path| match RotatingFile::open(&path) {
+        Ok(file) => Some(Mutex::new(file)),
+        Err(e) => {
+            eprintln!("Failed to open log file {:?}: {}", path, e);
+            None
+        }
+    });
+
+    let logger = Logger {
+        use_stderr: journald.is_none() || io::stderr().is_terminal(),
+        filter,
+        journald,
+        file,
+    };
+
+    let max_level = logger.filter.max_level();
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn matches_targets_by_module_prefix() {
+        let filter = Filter::parse("warn,audiosharegtk=debug,audiosharegtk::as_cmd=error");
+
+        assert_eq!(filter.level_for("audiosharegtk"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("audiosharegtk::firewall"), LevelFilter::Debug);
+        // The most specific target wins
+        assert_eq!(filter.level_for("audiosharegtk::as_cmd"), LevelFilter::Error);
+        // Only whole path segments count
+        assert_eq!(filter.level_for("audiosharegtk_extra"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("mdns_sd::service_daemon"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn bare_levels_and_targets() {
+        let filter = Filter::parse("info");
+        assert_eq!(filter.level_for("anything"), LevelFilter::Info);
+
+        let filter = Filter::parse("audiosharegtk");
+        assert_eq!(filter.level_for("audiosharegtk::logging"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("glib"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn skips_invalid_directives() {
+        let filter = Filter::parse(" , audiosharegtk=loud,,=info, error ");
+        assert_eq!(filter.level_for("audiosharegtk"), LevelFilter::Error);
+        assert_eq!(filter.max_level(), LevelFilter::Error);
+
+        let filter = Filter::parse("");
+        assert_eq!(filter.level_for("audiosharegtk"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn rotates_past_the_size_limit() {
+        let dir = TempDir::new("logging");
+        let path = dir.join("audiosharegtk.log");
+        let mut file = RotatingFile::open(&path).unwrap();
+
+        // Ten lines fill a file, so this rotates four times
+        let line_size = MAX_LOG_FILE_SIZE as usize / 10;
+        for index in 0..45 {
+            let mut line = format!("{:06} ", index);
+            line.push_str(&"x".repeat(line_size - line.len() - 1));
+            line.push('\n');
+            file.write_line(&line).unwrap();
+        }
+
+        let first_line = |index: usize| -> u32 {
+            let contents = fs::read_to_string(rotated_path(&path, index)).unwrap();
+            assert!(contents.len() as u64 <= MAX_LOG_FILE_SIZE);
+            contents[..6].parse().unwrap()
+        };
+
+        // Newest first, the oldest file was dropped
+        assert_eq!(fs::read_to_string(&path).unwrap()[..6].parse::<u32>().unwrap(), 40);
+        assert_eq!(first_line(1), 30);
+        assert_eq!(first_line(2), 20);
+        assert_eq!(first_line(3), 10);
+        assert!(!rotated_path(&path, KEPT_LOG_FILES + 1).exists());
+    }
+
+    #[test]
+    fn picks_up_an_existing_file_size() {
+        let dir = TempDir::new("logging-reopen");
+        let path = dir.join("audiosharegtk.log");
+        fs::write(&path, "x".repeat(MAX_LOG_FILE_SIZE as usize - 1)).unwrap();
+
+        let mut file = RotatingFile::open(&path).unwrap();
+        file.write_line("too much\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "too much\n");
+        assert!(rotated_path(&path, 1).exists());
+    }
+
+    #[test]
+    fn encodes_journald_fields() {
+        let mut message = Vec::new();
+        append_journald_field(&mut message, "PRIORITY", "6");
+        append_journald_field(&mut message, "MESSAGE", "two\nlines");
+
+        let mut expected = b"PRIORITY=6\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&9u64.to_le_bytes());
+        expected.extend_from_slice(b"two\nlines\n");
+        assert_eq!(message, expected);
+    }
+}
@@ -18,11 +18,24 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
+mod alerts;
 mod application;
 mod config;
 mod configfile;
+mod devices;
+mod diagnostics;
+mod discovery;
+mod firewall;
+mod history;
+mod log_viewer;
+mod logging;
+mod notifications;
+mod pairing;
+mod pidfile;
 mod audioshare;
 mod apputils;
+#[cfg(test)]
+mod test_support;
 mod window;
 
 use self::application::AudiosharegtkApplication;
@@ -34,6 +47,8 @@ use gtk::{gio, glib};
 use gtk::prelude::*;
 
 fn main() -> glib::ExitCode {
+    logging::init();
+
     // Set up gettext translations
     bindtextdomain(GETTEXT_PACKAGE, LOCALEDIR).expect("Unable to bind the text domain");
     bind_textdomain_codeset(GETTEXT_PACKAGE, "UTF-8")
@@ -50,8 +65,6 @@ fn main() -> glib::ExitCode {
     // desktop features such as file opening and single-instance applications.
     let app = AudiosharegtkApplication::new("com.subrighteous.audiosharegtk", &gio::ApplicationFlags::empty());
 
-    audioshare::get_version();
-
     // Run the application. This function will block until the application
     // exits. Upon return, we have our exit code to return to the shell. (This
     // is the code you see when you do `echo $?` after running a command in a
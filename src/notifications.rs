@@ -0,0 +1,362 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use adw::prelude::*;
+use gettextrs::{gettext, ngettext};
+use gtk::{gio, glib};
+use log::debug;
+
+use crate::alerts::{self, AlertEvent};
+use crate::apputils::{self, NotificationButton};
+use crate::configfile::AlertStyle;
+use crate::devices;
+
+// Wait this long for things to settle before notifying
+const DEBOUNCE: Duration = Duration::from_secs(3);
+// but never hold back a notification longer than this
+const MAX_DELAY: Duration = Duration::from_secs(15);
+// This many devices changing at once get one summary notification
+const SUMMARY_THRESHOLD: usize = 3;
+
+const SUMMARY_ID: &str = "devices-summary";
+
+// Every device gets its own notification, replaced by its next event
+fn device_notification_id(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("device-{}", key)
+}
+
+// A client connecting or disconnecting
+#[derive(Debug, Clone)]
+pub struct DeviceEvent {
+    pub key: String,
+    pub ip: String,
+    pub name: String,
+    pub connected: bool,
+    // Unnamed devices get a button to block them
+    pub known: bool,
+    // Whether the user wants a notification for this kind of event
+    pub notify: bool,
+    // and which other alerts
+    pub alerts: Vec<AlertStyle>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // Latest event per device since the last flush, oldest first
+    pending: RefCell<Vec<DeviceEvent>>,
+    pending_since: RefCell<Option<Instant>>,
+    // The last state the user was told about
+    notified: RefCell<HashMap<String, bool>>,
+    timer: RefCell<Option<glib::SourceId>>,
+}
+
+// Restart the debounce window unless things have been held long enough
+fn debounce_delay(held_for: Duration) -> Duration {
+    DEBOUNCE.min(MAX_DELAY.saturating_sub(held_for))
+}
+
+// What one flush tells the user, worked out before anything is shown
+#[derive(Debug, Default)]
+struct FlushPlan {
+    // Each with a notification of its own
+    devices: Vec<DeviceEvent>,
+    // All of them in one notification
+    summary: Vec<DeviceEvent>,
+    alerts: Vec<(AlertEvent, Vec<AlertStyle>, String)>,
+}
+
+// Collects device events and turns them into as few notifications as
+// possible. A device that drops and comes back within the debounce window
+// ends up where the user last saw it, so nothing is shown for it at all.
+#[derive(Debug, Clone, Default)]
+pub struct NotificationCoalescer {
+    inner: Rc<Inner>,
+}
+
+impl NotificationCoalescer {
+    pub fn push(&self, app: &impl IsA<gio::Application>, event: DeviceEvent) {
+        {
+            let mut pending = self.inner.pending.borrow_mut();
+            pending.retain(|pending| pending.key != event.key);
+            pending.push(event);
+        }
+
+        let pending_since = *self
+            .inner
+            .pending_since
+            .borrow_mut()
+            .get_or_insert_with(Instant::now);
+
+        let delay = debounce_delay(pending_since.elapsed());
+        if let Some(timer) = self.inner.timer.borrow_mut().take() {
+            timer.remove();
+        }
+
+        let coalescer = self.clone();
+        let app = app.upcast_ref::<gio::Application>().downgrade();
+        let timer = glib::timeout_add_local_once(delay, move || {
+            // The source is gone once this runs, so it must not be removed again
+            coalescer.inner.timer.borrow_mut().take();
+            if let Some(app) = app.upgrade() {
+                coalescer.flush(&app);
+            }
+        });
+        *self.inner.timer.borrow_mut() = Some(timer);
+    }
+
+    // Forget every device. as-cmd is killed without reporting its clients
+    // as closed, so once the server stops nobody is connected any more and
+    // the next connect must be shown again.
+    pub fn reset(&self) {
+        if let Some(timer) = self.inner.timer.borrow_mut().take() {
+            timer.remove();
+        }
+        self.inner.pending.borrow_mut().clear();
+        self.inner.pending_since.borrow_mut().take();
+        self.inner.notified.borrow_mut().clear();
+    }
+
+    // The pending events that differ from what the user was last told
+    fn take_changes(&self) -> Vec<DeviceEvent> {
+        let events: Vec<DeviceEvent> = self.inner.pending.borrow_mut().drain(..).collect();
+        self.inner.pending_since.borrow_mut().take();
+
+        let mut notified = self.inner.notified.borrow_mut();
+        events
+            .into_iter()
+            .filter(|event| notified.insert(event.key.clone(), event.connected) != Some(event.connected))
+            .collect()
+    }
+
+    fn flush(&self, app: &gio::Application) {
+        let plan = Self::plan(self.take_changes());
+        debug!("Flushing {} device notification(s)", plan.devices.len() + plan.summary.len());
+
+        if !plan.summary.is_empty() {
+            self.send_summary(app, &plan.summary);
+        }
+        for event in &plan.devices {
+            Self::send_device(app, event);
+        }
+        for (event_type, styles, message) in &plan.alerts {
+            alerts::raise(app, *event_type, styles, message);
+        }
+    }
+
+    fn plan(changes: Vec<DeviceEvent>) -> FlushPlan {
+        let mut plan = FlushPlan::default();
+
+        // One round of alerts per direction, however many devices changed
+        for (connected, event_type) in [(true, AlertEvent::DeviceConnected), (false, AlertEvent::DeviceDisconnected)] {
+            let events: Vec<&DeviceEvent> = changes.iter().filter(|event| event.connected == connected).collect();
+
+            let mut styles: Vec<AlertStyle> = Vec::new();
+            for style in events.iter().flat_map(|event| event.alerts.iter()) {
+                if !styles.contains(style) {
+                    styles.push(*style);
+                }
+            }
+
+            if !styles.is_empty() {
+                plan.alerts.push((event_type, styles, Self::describe(&events, connected)));
+            }
+        }
+
+        let notify: Vec<DeviceEvent> = changes.into_iter().filter(|event| event.notify).collect();
+        if notify.len() >= SUMMARY_THRESHOLD {
+            plan.summary = notify;
+        } else {
+            plan.devices = notify;
+        }
+
+        plan
+    }
+
+    // "Phone connected to the server" or "3 devices connected"
+    fn describe(events: &[&DeviceEvent], connected: bool) -> String {
+        match events {
+            [event] if connected => event.name.clone() + " " + &gettext("connected to the server"),
+            [event] => event.name.clone() + " " + &gettext("disconnected from the server"),
+            _ => {
+                let count = events.len() as u32;
+                let text = if connected {
+                    ngettext("device connected", "devices connected", count)
+                } else {
+                    ngettext("device disconnected", "devices disconnected", count)
+                };
+                count.to_string() + " " + &text
+            }
+        }
+    }
+
+    fn send_device(app: &gio::Application, event: &DeviceEvent) {
+        let title = if event.connected {
+            gettext("Device Connected")
+        } else {
+            gettext("Device Disconnected")
+        };
+        let message = Self::describe(&[event], event.connected);
+
+        let mut buttons = Vec::new();
+        if event.connected {
+            buttons.push(NotificationButton::new(gettext("Stop Server"), "app.stop_server"));
+            if !event.known {
+                buttons.push(NotificationButton::with_target(
+                    gettext("Block This Device"),
+                    "app.block_device",
+                    // A mapped address would become an IPv6 rule that never matches
+                    devices::plain_ip(&event.ip).to_variant(),
+                ));
+            }
+        }
+
+        apputils::show_connection_notification(
+            app,
+            &device_notification_id(&event.key),
+            &title,
+            &message,
+            &event.connected,
+            &buttons,
+        );
+    }
+
+    // One notification standing in for many, e.g. "3 devices connected"
+    fn send_summary(&self, app: &gio::Application, changes: &[DeviceEvent]) {
+        for event in changes {
+            app.withdraw_notification(&device_notification_id(&event.key));
+        }
+
+        let connected: Vec<&DeviceEvent> = changes.iter().filter(|event| event.connected).collect();
+        let disconnected: Vec<&DeviceEvent> = changes.iter().filter(|event| !event.connected).collect();
+
+        let mut parts = Vec::new();
+        if !connected.is_empty() {
+            parts.push(Self::describe(&connected, true));
+        }
+        if !disconnected.is_empty() {
+            parts.push(Self::describe(&disconnected, false));
+        }
+
+        let names: Vec<&str> = changes.iter().map(|event| event.name.as_str()).collect();
+        let message = parts.join(", ") + "\n" + &names.join(", ");
+
+        apputils::show_connection_notification(
+            app,
+            SUMMARY_ID,
+            &gettext("Devices Changed"),
+            &message,
+            &!connected.is_empty(),
+            &[],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key: &str, connected: bool) -> DeviceEvent {
+        DeviceEvent {
+            key: key.to_string(),
+            ip: key.to_string(),
+            name: key.to_string(),
+            connected,
+            known: true,
+            notify: true,
+            alerts: Vec::new(),
+        }
+    }
+
+    fn changes_for(coalescer: &NotificationCoalescer, events: &[DeviceEvent]) -> Vec<(String, bool)> {
+        coalescer.inner.pending.borrow_mut().extend(events.iter().cloned());
+        coalescer
+            .take_changes()
+            .into_iter()
+            .map(|event| (event.key, event.connected))
+            .collect()
+    }
+
+    #[test]
+    fn skips_what_the_user_already_saw() {
+        let coalescer = NotificationCoalescer::default();
+        assert_eq!(changes_for(&coalescer, &[event("10.0.0.5", true)]), [("10.0.0.5".to_string(), true)]);
+        assert!(changes_for(&coalescer, &[event("10.0.0.5", true)]).is_empty());
+    }
+
+    #[test]
+    fn shows_a_reconnect_after_a_restart() {
+        let coalescer = NotificationCoalescer::default();
+        assert_eq!(changes_for(&coalescer, &[event("10.0.0.5", true)]).len(), 1);
+
+        // The server goes down without as-cmd reporting the client as closed
+        coalescer.reset();
+
+        assert_eq!(changes_for(&coalescer, &[event("10.0.0.5", true)]), [("10.0.0.5".to_string(), true)]);
+    }
+
+    fn keys(events: &[DeviceEvent]) -> Vec<&str> {
+        events.iter().map(|event| event.key.as_str()).collect()
+    }
+
+    #[test]
+    fn summarizes_three_or_more_devices() {
+        let plan = NotificationCoalescer::plan(vec![event("a", true), event("b", true), event("c", false)]);
+        assert!(plan.devices.is_empty());
+        assert_eq!(keys(&plan.summary), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn notifies_two_devices_one_by_one() {
+        let plan = NotificationCoalescer::plan(vec![event("a", true), event("b", false)]);
+        assert_eq!(keys(&plan.devices), ["a", "b"]);
+        assert!(plan.summary.is_empty());
+    }
+
+    #[test]
+    fn leaves_out_devices_without_notifications() {
+        let mut quiet = event("c", true);
+        quiet.notify = false;
+        // Only two of the three want a notification, so no summary
+        let plan = NotificationCoalescer::plan(vec![event("a", true), event("b", true), quiet]);
+        assert_eq!(keys(&plan.devices), ["a", "b"]);
+    }
+
+    #[test]
+    fn alerts_once_per_direction() {
+        let mut events = vec![event("a", true), event("b", true), event("c", true), event("d", false)];
+        events[0].alerts = vec![AlertStyle::Sound];
+        events[1].alerts = vec![AlertStyle::Sound, AlertStyle::Attention];
+        events[3].alerts = vec![AlertStyle::Sound];
+
+        let plan = NotificationCoalescer::plan(events);
+        let alerts: Vec<(AlertEvent, Vec<AlertStyle>)> = plan.alerts.into_iter().map(|(event, styles, _)| (event, styles)).collect();
+        assert_eq!(
+            alerts,
+            [
+                (AlertEvent::DeviceConnected, vec![AlertStyle::Sound, AlertStyle::Attention]),
+                (AlertEvent::DeviceDisconnected, vec![AlertStyle::Sound]),
+            ]
+        );
+    }
+
+    #[test]
+    fn caps_the_debounce() {
+        assert_eq!(debounce_delay(Duration::ZERO), DEBOUNCE);
+        assert_eq!(debounce_delay(MAX_DELAY - Duration::from_secs(1)), Duration::from_secs(1));
+        assert_eq!(debounce_delay(MAX_DELAY * 2), Duration::ZERO);
+    }
+
+    #[test]
+    fn reset_drops_pending_events() {
+        let coalescer = NotificationCoalescer::default();
+        coalescer.inner.pending.borrow_mut().push(event("10.0.0.5", false));
+        coalescer.reset();
+        assert!(coalescer.take_changes().is_empty());
+    }
+}
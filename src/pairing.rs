@@ -0,0 +1,105 @@
+use std::net::{IpAddr, SocketAddr};
+
+use gtk::prelude::*;
+use gtk::{gdk, glib};
+use log::error;
+use qrcode::{Color, QrCode};
+
+use crate::audioshare::{self, ServerBinding};
+
+// Pixels per QR module and the quiet zone around the code in modules
+const MODULE_SIZE: usize = 8;
+const QUIET_ZONE: usize = 4;
+
+// The address a client should dial to reach the server
+pub fn pairing_address(binding: &ServerBinding) -> String {
+    match binding.server_ip.parse::<IpAddr>() {
+        // A phone can't dial the wildcard address, give it the LAN one
+        Ok(ip) if ip.is_unspecified() => {
+            let lan_ip = audioshare::get_local_ipv4().parse().unwrap_or(ip);
+            SocketAddr::new(lan_ip, binding.server_port).to_string()
+        }
+        // Brackets IPv6 addresses
+        Ok(ip) => SocketAddr::new(ip, binding.server_port).to_string(),
+        Err(_) => format!("{}:{}", binding.server_ip, binding.server_port),
+    }
+}
+
+// The text encoded in the pairing QR code
+pub fn pairing_uri(binding: &ServerBinding) -> String {
+    format!("audioshare://{}?encoding={}", pairing_address(binding), binding.encoding_key)
+}
+
+// Render the data as a black on white QR code texture
+pub fn render_qr_code(data: &str) -> Option<gdk::Texture> {
+    let code = match QrCode::new(data.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            error!("Failed to create QR code: {}", e);
+            return None;
+        }
+    };
+
+    let modules = code.width();
+    let colors = code.to_colors();
+    let size = (modules + QUIET_ZONE * 2) * MODULE_SIZE;
+    let stride = size * 3;
+
+    // Start all white and paint the dark modules in
+    let mut pixels = vec![0xffu8; stride * size];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+
+        let x = (index % modules + QUIET_ZONE) * MODULE_SIZE;
+        let y = (index / modules + QUIET_ZONE) * MODULE_SIZE;
+        for row in y..y + MODULE_SIZE {
+            let start = row * stride + x * 3;
+            pixels[start..start + MODULE_SIZE * 3].fill(0);
+        }
+    }
+
+    let texture = gdk::MemoryTexture::new(
+        size as i32,
+        size as i32,
+        gdk::MemoryFormat::R8g8b8,
+        &glib::Bytes::from_owned(pixels),
+        stride,
+    );
+
+    Some(texture.upcast())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(server_ip: &str) -> ServerBinding {
+        ServerBinding {
+            server_ip: server_ip.to_string(),
+            server_port: 65530,
+            endpoint_id: 0,
+            encoding_key: "pcm_16bit".to_string(),
+        }
+    }
+
+    #[test]
+    fn uri_for_an_ipv4_bind() {
+        assert_eq!(pairing_uri(&binding("192.168.1.10")), "audioshare://192.168.1.10:65530?encoding=pcm_16bit");
+    }
+
+    #[test]
+    fn uri_brackets_ipv6() {
+        assert_eq!(pairing_uri(&binding("fd00::2")), "audioshare://[fd00::2]:65530?encoding=pcm_16bit");
+    }
+
+    #[test]
+    fn uri_never_points_at_the_wildcard() {
+        for wildcard in ["0.0.0.0", "::"] {
+            let uri = pairing_uri(&binding(wildcard));
+            let expected = format!("audioshare://{}:65530?encoding=pcm_16bit", audioshare::get_local_ipv4());
+            assert_eq!(uri, expected);
+        }
+    }
+}
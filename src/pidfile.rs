@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use directories::ProjectDirs;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::audioshare::ServerBinding;
+
+// Runtime files are gone after a reboot, and so is any as-cmd
+pub fn get_pid_path() -> Option<PathBuf> {
+    ProjectDirs::from("com", "subrighteous", "AudioShareGTK")
+        .and_then(|dirs| dirs.runtime_dir().map(|dir| dir.join("as-cmd.pid")))
+}
+
+// The as-cmd process the app started, and what it serves
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PidRecord {
+    pub pid: u32,
+    pub binding: ServerBinding,
+}
+
+pub fn save(record: &PidRecord) -> io::Result<()> {
+    let path = get_pid_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No runtime directory"))?;
+    save_to(&path, record)
+}
+
+pub fn remove() {
+    if let Some(path) = get_pid_path() {
+        remove_at(&path);
+    }
+}
+
+// Whether pid is a live as-cmd. Checking the name guards against the pid
+// having been handed to another process since.
+pub fn is_as_cmd(pid: u32) -> bool {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|comm| comm.trim() == "as-cmd")
+        .unwrap_or(false)
+}
+
+// An as-cmd left running by an earlier session that crashed or was killed.
+// A pid file pointing at anything else is stale and removed.
+pub fn find_orphan() -> Option<PidRecord> {
+    find_orphan_at(&get_pid_path()?)
+}
+
+fn save_to(path: &Path, record: &PidRecord) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, serde_json::to_string(record)?)
+}
+
+fn load_from(path: &Path) -> Option<PidRecord> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            warn!("Ignoring unreadable pid file: {}", e);
+            None
+        }
+    }
+}
+
+fn remove_at(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!("Failed to remove the pid file: {}", e);
+        }
+    }
+}
+
+fn find_orphan_at(path: &Path) -> Option<PidRecord> {
+    let record = load_from(path)?;
+
+    if !is_as_cmd(record.pid) {
+        debug!("Removing stale pid file for {}", record.pid);
+        remove_at(path);
+        return None;
+    }
+
+    warn!("as-cmd {} from an earlier session is still running", record.pid);
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TempDir};
+
+    fn record(pid: u32) -> PidRecord {
+        PidRecord {
+            pid,
+            binding: test_support::binding("192.168.1.10"),
+        }
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let dir = TempDir::new("pidfile-roundtrip");
+        let path = dir.join("as-cmd.pid");
+        save_to(&path, &record(1234)).unwrap();
+        assert_eq!(load_from(&path), Some(record(1234)));
+
+        remove_at(&path);
+        assert_eq!(load_from(&path), None);
+    }
+
+    #[test]
+    fn removes_a_stale_record() {
+        let dir = TempDir::new("pidfile-stale");
+        let path = dir.join("as-cmd.pid");
+        // The test itself is running, but it isn't as-cmd
+        save_to(&path, &record(std::process::id())).unwrap();
+
+        assert_eq!(find_orphan_at(&path), None);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn ignores_an_unreadable_record() {
+        let dir = TempDir::new("pidfile-unreadable");
+        let path = dir.join("as-cmd.pid");
+        save_to(&path, &record(1)).unwrap();
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(find_orphan_at(&path), None);
+    }
+}
@@ -0,0 +1,55 @@
+// Fixtures shared by the unit tests
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::audioshare::ServerBinding;
+use crate::history::SessionRecord;
+
+pub const PORT: u16 = 65530;
+pub const ENCODING: &str = "pcm_16bit";
+pub const CLIENT_ADDRESS: &str = "192.168.1.20:50412";
+
+pub fn binding(server_ip: &str) -> ServerBinding {
+    ServerBinding {
+        server_ip: server_ip.to_string(),
+        server_port: PORT,
+        endpoint_id: 0,
+        encoding_key: ENCODING.to_string(),
+    }
+}
+
+// A minute long session that started at the given unix time
+pub fn session_record(started: u64) -> SessionRecord {
+    SessionRecord {
+        address: CLIENT_ADDRESS.to_string(),
+        started,
+        ended: started + 60,
+        duration_secs: 60,
+        endpoint_id: 0,
+        encoding: ENCODING.to_string(),
+    }
+}
+
+// A directory of its own for one test, removed again on drop so a failing
+// assert doesn't leave it behind
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("audiosharegtk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create a temporary directory");
+        Self(dir)
+    }
+
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -19,12 +19,23 @@
  */
 
 use gtk::prelude::*;
+use adw::prelude::*;
 use adw::subclass::prelude::*;
+use gettextrs::gettext;
 use gtk::{gio, glib};
 
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
 use once_cell::sync::OnceCell;
+use crate::audioshare::{self, ServerBinding, ServerStats};
 use crate::configfile::AppConfig;
+use crate::devices;
+use crate::history::SessionRecord;
+use crate::pairing;
+
+// How long an alert stays in the banner
+const ALERT_BANNER_SECONDS: u32 = 10;
 
 mod imp {
     use super::*;
@@ -61,8 +72,42 @@ mod imp {
         #[template_child(id = "AudioEncoding_Box")]
         pub audio_encoding_box: TemplateChild<gtk::Box>,
 
+        // Shown when as-cmd is missing or unsupported
+        #[template_child(id = "as_cmd_banner")]
+        pub as_cmd_banner: TemplateChild<adw::Banner>,
+
+        // Alerts picked in the preferences, for when notifications can't be seen
+        #[template_child(id = "alert_banner")]
+        pub alert_banner: TemplateChild<adw::Banner>,
+        pub alert_banner_timeout: RefCell<Option<glib::SourceId>>,
+        pub alert_sound: RefCell<Option<gtk::MediaFile>>,
+
+        // Live server statistics, only visible while running
+        #[template_child(id = "stats_list")]
+        pub stats_list: TemplateChild<gtk::ListBox>,
+
+        #[template_child(id = "stats_uptime_row")]
+        pub stats_uptime_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "stats_clients_row")]
+        pub stats_clients_row: TemplateChild<adw::ActionRow>,
+
+        #[template_child(id = "stats_encoding_row")]
+        pub stats_encoding_row: TemplateChild<adw::ActionRow>,
+
+        // Connection history page
+        #[template_child(id = "history_list")]
+        pub history_list: TemplateChild<gtk::ListBox>,
+
         pub test_firewall_button: gtk::Button,
 
+        // Device keys by client IP, looked up once when the client connects
+        pub device_keys: RefCell<HashMap<String, String>>,
+
+        // Pairing dialog widgets, only set while the dialog is open
+        pub pairing_picture: RefCell<Option<gtk::Picture>>,
+        pub pairing_label: RefCell<Option<gtk::Label>>,
+
         //pub label: TemplateChild<gtk::Label>
     }
 
@@ -81,7 +126,13 @@ mod imp {
         }
     }
 
-    impl ObjectImpl for AudiosharegtkWindow {}
+    impl ObjectImpl for AudiosharegtkWindow {
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            self.alert_banner.connect_button_clicked(|banner| banner.set_revealed(false));
+        }
+    }
     impl WidgetImpl for AudiosharegtkWindow {}
     impl WindowImpl for AudiosharegtkWindow {}
     impl ApplicationWindowImpl for AudiosharegtkWindow {}
@@ -112,4 +163,179 @@ impl AudiosharegtkWindow {
             .property("application", application)
             .build()
     }
+
+    // Show a QR code clients can scan instead of typing in the address
+    pub fn show_pairing_dialog(&self, binding: Option<&ServerBinding>) {
+        let picture = gtk::Picture::builder()
+            .width_request(256)
+            .height_request(256)
+            .can_shrink(true)
+            .content_fit(gtk::ContentFit::Contain)
+            .build();
+
+        let label = gtk::Label::builder()
+            .wrap(true)
+            .justify(gtk::Justification::Center)
+            .selectable(true)
+            .build();
+
+        let content = gtk::Box::builder()
+            .orientation(gtk::Orientation::Vertical)
+            .spacing(18)
+            .margin_top(12)
+            .margin_bottom(24)
+            .margin_start(24)
+            .margin_end(24)
+            .build();
+        content.append(&picture);
+        content.append(&label);
+
+        let toolbar_view = adw::ToolbarView::new();
+        toolbar_view.add_top_bar(&adw::HeaderBar::new());
+        toolbar_view.set_content(Some(&content));
+
+        let dialog = adw::Dialog::builder()
+            .title(gettext("Pair a Device"))
+            .content_width(360)
+            .child(&toolbar_view)
+            .build();
+
+        dialog.connect_closed(glib::clone!(
+            #[weak(rename_to = win)]
+            self,
+            move |_| {
+                win.imp().pairing_picture.replace(None);
+                win.imp().pairing_label.replace(None);
+            }
+        ));
+
+        self.imp().pairing_picture.replace(Some(picture));
+        self.imp().pairing_label.replace(Some(label));
+        self.update_pairing(binding);
+
+        dialog.present(Some(self));
+    }
+
+    // Show a message in the banner for a while
+    pub fn show_alert_banner(&self, message: &str) {
+        self.imp().alert_banner.set_title(message);
+        self.imp().alert_banner.set_revealed(true);
+
+        if let Some(timeout) = self.imp().alert_banner_timeout.take() {
+            timeout.remove();
+        }
+
+        let timeout = glib::timeout_add_seconds_local_once(ALERT_BANNER_SECONDS, glib::clone!(
+            #[weak(rename_to = win)]
+            self,
+            move || {
+                win.imp().alert_banner_timeout.take();
+                win.imp().alert_banner.set_revealed(false);
+            }
+        ));
+        self.imp().alert_banner_timeout.replace(Some(timeout));
+    }
+
+    // Play a short sound. The media file is kept until the next one so it
+    // isn't dropped halfway through.
+    pub fn play_alert_sound(&self, path: &Path) {
+        let media = gtk::MediaFile::for_filename(path);
+        media.play();
+        self.imp().alert_sound.replace(Some(media));
+    }
+
+    // Show the server statistics, hiding them while the server is stopped
+    pub fn update_stats(&self, stats: &ServerStats) {
+        let Some(uptime) = stats.uptime() else {
+            self.imp().stats_list.set_visible(false);
+            return;
+        };
+
+        let seconds = uptime.as_secs();
+        self.imp().stats_uptime_row.set_subtitle(&format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            (seconds / 60) % 60,
+            seconds % 60
+        ));
+
+        let clients = if stats.clients.is_empty() {
+            gettext("None")
+        } else {
+            let names: Vec<String> = match self.imp().config.get() {
+                Some(config) => {
+                    let config = config.borrow();
+                    stats
+                        .clients
+                        .iter()
+                        .map(|client| {
+                            let ip = audioshare::client_ip(client).map(|ip| ip.to_string()).unwrap_or_else(|| client.clone());
+                            let key = self.imp().device_keys.borrow().get(&ip).cloned().unwrap_or(ip);
+                            devices::nickname(&config.device_names, &key)
+                                .map(|name| name.to_string() + " (" + client + ")")
+                                .unwrap_or_else(|| client.clone())
+                        })
+                        .collect()
+                }
+                None => stats.clients.clone(),
+            };
+            stats.clients.len().to_string() + " (" + &names.join(", ") + ")"
+        };
+        self.imp().stats_clients_row.set_subtitle(&clients);
+
+        self.imp()
+            .stats_encoding_row
+            .set_subtitle(stats.encoding_key.as_deref().unwrap_or_default());
+
+        self.imp().stats_list.set_visible(true);
+    }
+
+    // Show a history record at the top of the history page
+    pub fn add_history_record(&self, record: &SessionRecord) {
+        let format_time = |secs: u64| {
+            glib::DateTime::from_unix_local(secs as i64)
+                .and_then(|time| time.format("%Y-%m-%d %H:%M:%S"))
+                .map(|time| time.to_string())
+                .unwrap_or_default()
+        };
+
+        let duration = format!(
+            "{:02}:{:02}:{:02}",
+            record.duration_secs / 3600,
+            (record.duration_secs / 60) % 60,
+            record.duration_secs % 60
+        );
+
+        let row = adw::ActionRow::builder()
+            .title(&record.address)
+            .subtitle(format_time(record.started) + " – " + &format_time(record.ended) + "  ·  " + &duration + "  ·  " + &record.encoding)
+            .build();
+
+        self.imp().history_list.prepend(&row);
+    }
+
+    // Refresh the pairing dialog, if it is open, with the live server address
+    pub fn update_pairing(&self, binding: Option<&ServerBinding>) {
+        let picture = self.imp().pairing_picture.borrow();
+        let label = self.imp().pairing_label.borrow();
+        let (Some(picture), Some(label)) = (picture.as_ref(), label.as_ref()) else {
+            return;
+        };
+
+        match binding {
+            Some(binding) => {
+                let uri = pairing::pairing_uri(binding);
+                picture.set_paintable(pairing::render_qr_code(&uri).as_ref());
+                picture.set_visible(true);
+                label.set_text(&(gettext("Scan with the Audio Share app or connect to")
+                    + "\n" + &pairing::pairing_address(binding)
+                    + "\n" + &gettext("Encoding:") + " " + &binding.encoding_key));
+            }
+            None => {
+                picture.set_paintable(None::<&gtk::gdk::Paintable>);
+                picture.set_visible(false);
+                label.set_text(&gettext("Start the server to pair a device."));
+            }
+        }
+    }
 }